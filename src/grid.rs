@@ -1,8 +1,9 @@
 // vim: set ai et ts=4 sts=4:
 use std::fmt;
+use std::error;
 use std::convert::{From, TryFrom};
-use std::rc::{Rc};
-use std::cell::{RefCell};
+use std::sync::Arc;
+use parking_lot::RwLock;
 use super::util::{Direction, Direction::*};
 use super::row::Run;
 
@@ -178,14 +179,27 @@ impl fmt::Display for RunError {
     }
 }
 
+impl error::Error for StatusError {}
+impl error::Error for RunError {}
+
 pub type StatusResult = Result<Option<StatusChange>, StatusError>; // if it worked: the change, if any; if it didn't, the change that was rejected
 pub type RunResult    = Result<Option<RunChange>, RunError>; // ditto
 
-#[derive(Debug)]
+#[derive(PartialEq, Debug)]
 pub enum Error {
     Status(StatusError),
     Run(RunError),
     Logic(String),
+    Contradiction {                        // a row turned out to be unsolvable in its current state
+        direction: Direction,
+        index: usize,
+        reason: String,
+        conflicting_squares: Vec<(usize, usize)>, // (x,y) locations of the squares that caused it
+    },
+    Timeout,                               // solver deadline passed before solving finished
+    Cancelled,                             // solving was cancelled through the solver's cancel flag
+    DepthLimit,                            // solving would require nesting guesses deeper than allowed
+    IterationLimit,                        // solver exceeded its maximum number of iterations
 }
 impl From<StatusError> for Error {
     fn from(other: StatusError) -> Self {
@@ -203,9 +217,32 @@ impl fmt::Display for Error {
             Error::Status(x) => x.to_string(),
             Error::Run(x)    => x.to_string(),
             Error::Logic(s)  => s.to_string(),
+            Error::Contradiction { direction, index, reason, conflicting_squares } => format!(
+                "Inconsistency in {} row {}: {}{}", direction, index, reason,
+                match conflicting_squares.len() {
+                    0 => String::new(),
+                    _ => format!(" (conflicting squares: {})",
+                                 conflicting_squares.iter()
+                                                    .map(|(x, y)| format!("(x={}, y={})", x, y))
+                                                    .collect::<Vec<_>>()
+                                                    .join(", ")),
+                }),
+            Error::Timeout   => "Timeout: solver deadline exceeded".to_string(),
+            Error::Cancelled => "Cancelled: solving was aborted on request".to_string(),
+            Error::DepthLimit => "Depth limit: solving requires nesting guesses deeper than allowed".to_string(),
+            Error::IterationLimit => "Iteration limit: solver exceeded its maximum number of iterations".to_string(),
         })
     }
 }
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Status(x) => Some(x),
+            Error::Run(x)    => Some(x),
+            _                => None,
+        }
+    }
+}
 
 // ------------------------------------------------
 
@@ -237,6 +274,16 @@ impl Square {
         self.apply_status_change(cand_change)
     }
 
+    pub fn transposed(&self) -> Square {
+        // copy of this square as it appears in the transposed grid, where rows and columns are swapped
+        Square {
+            row: self.col,
+            col: self.row,
+            status: self.status,
+            hrun_index: self.vrun_index,
+            vrun_index: self.hrun_index,
+        }
+    }
     pub fn get_run_index(&self, direction: Direction) -> Option<usize> {
         match direction {
             Horizontal => self.hrun_index,
@@ -250,6 +297,8 @@ impl Square {
         self.apply_run_change(cand_change)
     }
     pub fn assign_run(&mut self, run: &Run) -> RunResult {
+        // note: callers typically hold a mutable borrow on the grid (through this square) while
+        // calling this, so this must only look at the run's identity and never access its grid.
         self.set_run_index(run.direction, run.index)
     }
     pub fn has_run_assigned(&self, run: &Run) -> bool {
@@ -344,16 +393,28 @@ pub struct Grid {
 }
 impl Grid {
     pub fn new(width: usize, height: usize)
-        -> Self
+        -> Result<Self, Error>
     {
-        Grid {
+        if width == 0 || height == 0 {
+            return Err(Error::Logic(format!("Grid must be at least 1x1 (got {}x{})", width, height)));
+        }
+        Ok(Grid {
             squares: (0..height).map(|y| (0..width).map(|x| Square::new(x, y))
                                                    .collect::<Vec<_>>())
                                 .collect(),
+        })
+    }
+
+    pub fn transpose(&self) -> Grid {
+        // returns a copy of this grid with rows and columns swapped
+        Grid {
+            squares: (0..self.width()).map(|x| (0..self.height()).map(|y| self.squares[y][x].transposed())
+                                                                 .collect::<Vec<_>>())
+                                      .collect(),
         }
     }
 
-    pub fn width(&self) -> usize { self.squares[0].len() }
+    pub fn width(&self) -> usize { self.squares.first().map_or(0, |row| row.len()) }
     pub fn height(&self) -> usize { self.squares.len() }
     pub fn get_square(&self, x: usize, y: usize) -> &Square {
         &self.squares[y][x]
@@ -361,6 +422,100 @@ impl Grid {
     pub fn get_square_mut(&mut self, x: usize, y: usize) -> &mut Square {
         &mut self.squares[y][x]
     }
+    pub fn status_matrix(&self) -> Vec<Vec<SquareStatus>> {
+        // owned snapshot of the status of every square, indexed as [y][x]
+        self.squares.iter()
+                    .map(|row| row.iter().map(|square| square.get_status()).collect())
+                    .collect()
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // compact binary form of the square statuses: the width and height as 32-bit little-endian integers,
+        // followed by two bits per square (0 = unknown, 1 = filled in, 2 = crossed out) in row-major order,
+        // packed four to a byte starting from the lowest bits. the last byte is padded with zeroes.
+        let mut result = Vec::<u8>::with_capacity(8 + (self.width() * self.height()).div_ceil(4));
+        result.extend(&(self.width() as u32).to_le_bytes());
+        result.extend(&(self.height() as u32).to_le_bytes());
+        for (i, square) in self.squares.iter().flatten().enumerate() {
+            let bits = match square.get_status() {
+                SquareStatus::Unknown    => 0u8,
+                SquareStatus::FilledIn   => 1u8,
+                SquareStatus::CrossedOut => 2u8,
+            };
+            if i % 4 == 0 {
+                result.push(0);
+            }
+            *result.last_mut().unwrap() |= bits << (2 * (i % 4));
+        }
+        result
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Grid, Error> {
+        // reads back a grid written by to_bytes
+        if bytes.len() < 8 {
+            return Err(Error::Logic(format!("Grid data is {} bytes long, too short to contain a header", bytes.len())));
+        }
+        let width = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+        let height = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+        // check the size claimed by the header against the data before allocating anything for it
+        let square_count = width.checked_mul(height).ok_or_else(|| Error::Logic(format!(
+            "Grid data claims a {}x{} grid, which is too large", width, height)))?;
+        let expected_len = square_count.div_ceil(4) + 8;
+        if bytes.len() != expected_len {
+            return Err(Error::Logic(format!(
+                "Grid data for a {}x{} grid should be {} bytes long, but is {}", width, height, expected_len, bytes.len())));
+        }
+        let mut grid = Grid::new(width, height)?;
+        for i in 0..square_count {
+            let status = match (bytes[8 + i / 4] >> (2 * (i % 4))) & 0b11 {
+                0 => continue,
+                1 => SquareStatus::FilledIn,
+                2 => SquareStatus::CrossedOut,
+                _ => return Err(Error::Logic(format!(
+                         "Invalid square status in grid data at (col={}, row={})", i % width, i / width))),
+            };
+            grid.get_square_mut(i % width, i / width).set_status(status)?;
+        }
+        Ok(grid)
+    }
+    pub fn diff(&self, other: &Grid) -> Result<Vec<(usize, usize, SquareStatus, SquareStatus)>, Error> {
+        // returns the (x, y) location and the status in both grids of every square whose status differs
+        // between this grid and the other one, in row-major order. both grids must be of the same size.
+        if self.width() != other.width() || self.height() != other.height() {
+            return Err(Error::Logic(format!("Cannot diff a {}x{} grid against a {}x{} grid",
+                self.width(), self.height(), other.width(), other.height())));
+        }
+        Ok(self.squares.iter().flatten()
+                       .zip(other.squares.iter().flatten())
+                       .filter(|(a, b)| a.get_status() != b.get_status())
+                       .map(|(a, b)| (a.get_col(), a.get_row(), a.get_status(), b.get_status()))
+                       .collect())
+    }
+    pub fn merge(&mut self, other: &Grid) -> Result<Changes, Error> {
+        // takes over every known square of the other grid that is still unknown in this one, and returns the
+        // resulting changes in row-major order. if the grids disagree on any square that's known in both, that's
+        // a contradiction and nothing is changed.
+        if self.width() != other.width() || self.height() != other.height() {
+            return Err(Error::Logic(format!("Cannot merge a {}x{} grid into a {}x{} grid",
+                other.width(), other.height(), self.width(), self.height())));
+        }
+        if let Some((x, y, ours, theirs)) = self.diff(other)?.into_iter()
+                                                .find(|&(_, _, ours, theirs)| ours != SquareStatus::Unknown && theirs != SquareStatus::Unknown)
+        {
+            return Err(StatusError::ChangeRejected(StatusChange::new(y, x, ours, theirs), "conflicting information".to_string()).into());
+        }
+
+        let mut changes = Changes::new();
+        for square in other.squares.iter().flatten() {
+            if square.get_status() == SquareStatus::Unknown {
+                continue;
+            }
+            let (x, y) = (square.get_col(), square.get_row());
+            let cand_change = StatusChange::new(y, x, self.get_square(x, y).get_status(), square.get_status());
+            if let Some(change) = self.get_square_mut(x, y).apply_status_change(cand_change)? {
+                changes.push(Change::from(change));
+            }
+        }
+        Ok(changes)
+    }
 }
 
 impl fmt::Debug for Grid {
@@ -372,7 +527,68 @@ impl fmt::Debug for Grid {
 // ------------------------------------------------
 
 pub trait CloneGridAware {
-    // clones a struct that carries an Rc<RefCell<Grid>>
-    fn clone_with_grid(&self, grid: &Rc<RefCell<Grid>>) -> Self;
+    // clones a struct that carries an Arc<RwLock<Grid>>
+    fn clone_with_grid(&self, grid: &Arc<RwLock<Grid>>) -> Self;
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterned_grid(width: usize, height: usize) -> Grid {
+        // a grid with a mix of all three statuses, so that every bit pattern gets written
+        let mut grid = Grid::new(width, height).unwrap();
+        for y in 0..height {
+            for x in 0..width {
+                let status = match (x + 2*y) % 3 {
+                    0 => continue,
+                    1 => SquareStatus::FilledIn,
+                    _ => SquareStatus::CrossedOut,
+                };
+                grid.get_square_mut(x, y).set_status(status).unwrap();
+            }
+        }
+        grid
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        // 8x8 fills up its last byte exactly, the others leave padding behind
+        for &(width, height) in &[(1, 1), (3, 5), (7, 9), (8, 8)] {
+            let grid = patterned_grid(width, height);
+            let bytes = grid.to_bytes();
+            assert_eq!(bytes.len(), 8 + (width * height).div_ceil(4));
+            let restored = Grid::from_bytes(&bytes).unwrap();
+            assert_eq!((restored.width(), restored.height()), (width, height));
+            assert_eq!(restored.status_matrix(), grid.status_matrix(), "{}x{}", width, height);
+        }
+    }
+
+    #[test]
+    fn truncated_bytes_are_rejected() {
+        let bytes = patterned_grid(3, 5).to_bytes();
+        for len in &[0, 7, 8, bytes.len() - 1] {
+            assert!(matches!(Grid::from_bytes(&bytes[..*len]), Err(Error::Logic(_))), "{} bytes", len);
+        }
+        let mut extended = bytes.clone();
+        extended.push(0);
+        assert!(matches!(Grid::from_bytes(&extended), Err(Error::Logic(_))));
+
+        // a bare header claiming a huge grid is rejected without building that grid first
+        let mut header = Vec::<u8>::new();
+        header.extend(&65535u32.to_le_bytes());
+        header.extend(&65535u32.to_le_bytes());
+        assert!(matches!(Grid::from_bytes(&header), Err(Error::Logic(_))));
+        let header = [0xffu8; 8];
+        assert!(matches!(Grid::from_bytes(&header), Err(Error::Logic(_))));
+    }
+
+    #[test]
+    fn invalid_status_bits_are_rejected() {
+        // 0b11 isn't any status; put it on the second square
+        let mut bytes = Grid::new(3, 5).unwrap().to_bytes();
+        bytes[8] |= 0b11 << 2;
+        assert!(matches!(Grid::from_bytes(&bytes), Err(Error::Logic(_))));
+    }
+}
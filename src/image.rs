@@ -0,0 +1,244 @@
+// vim: set ai et ts=4 sts=4 sw=4:
+use std::fs;
+use std::path::Path;
+
+use super::puzzle::Puzzle;
+use super::grid::Error;
+
+/// Support for creating puzzles out of black-and-white pictures.
+///
+/// Images are read in the netpbm formats (PBM, PGM and PPM, both plain and raw; i.e. P1 through P6),
+/// which most image tools can export to and which can be decoded without any extra dependencies.
+/// Pixels are converted to grayscale, optionally downscaled to the desired grid size by averaging,
+/// and then every pixel darker than the threshold becomes a filled in square.
+impl Puzzle {
+    pub fn from_image(path: &Path, threshold: u8, dimensions: Option<(usize, usize)>) -> Result<Puzzle, Error> {
+        // dimensions are given as (width, height) in squares; by default, every pixel becomes one square.
+        let data = fs::read(path).map_err(|e| Error::Logic(format!("Failed to read image {}: {}", path.display(), e)))?;
+        Puzzle::_from_netpbm(&data, threshold, dimensions)
+    }
+    fn _from_netpbm(data: &[u8], threshold: u8, dimensions: Option<(usize, usize)>) -> Result<Puzzle, Error> {
+        let mut image = GrayImage::from_netpbm(data)?;
+        if let Some((width, height)) = dimensions {
+            if width == 0 || height == 0 || width > image.width || height > image.height {
+                return Err(Error::Logic(format!(
+                    "Can't scale a {}x{} image to {}x{} squares", image.width, image.height, width, height)));
+            }
+            image = image.downscale(width, height);
+        }
+        let pixels = (0..image.height).map(|y| (0..image.width).map(|x| image.get(x, y) < threshold)
+                                                               .collect::<Vec<_>>())
+                                      .collect::<Vec<_>>();
+        Puzzle::from_pixels(&pixels)
+    }
+}
+
+struct GrayImage {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>, // row-major, 0 is black and 255 is white
+}
+impl GrayImage {
+    fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+    fn downscale(&self, width: usize, height: usize) -> GrayImage {
+        // each target pixel becomes the average of the block of source pixels it covers
+        let mut pixels = Vec::with_capacity(width * height);
+        for ty in 0..height {
+            for tx in 0..width {
+                let (x_start, x_end) = (tx * self.width / width, (tx + 1) * self.width / width);
+                let (y_start, y_end) = (ty * self.height / height, (ty + 1) * self.height / height);
+                let sum = (y_start..y_end).flat_map(|y| (x_start..x_end).map(move |x| (x, y)))
+                                          .map(|(x, y)| self.get(x, y) as usize)
+                                          .sum::<usize>();
+                pixels.push((sum / ((x_end - x_start) * (y_end - y_start))) as u8);
+            }
+        }
+        GrayImage { width, height, pixels }
+    }
+    fn from_netpbm(data: &[u8]) -> Result<GrayImage, Error> {
+        let mut reader = NetpbmReader { data, pos: 0 };
+        let magic = reader.token()?;
+        let width = reader.number()?;
+        let height = reader.number()?;
+        let maxval = match magic.as_str() {
+            "P1" | "P4" => 1,
+            "P2" | "P3" | "P5" | "P6" => reader.number()?,
+            _ => return Err(Error::Logic(format!("Unsupported image format '{}'; expected a netpbm (P1-P6) image", magic))),
+        };
+        if width == 0 || height == 0 || maxval == 0 || maxval > 65535 {
+            return Err(Error::Logic(format!("Invalid image header: {}x{}, maximum value {}", width, height, maxval)));
+        }
+        if matches!(magic.as_str(), "P4" | "P5" | "P6") {
+            reader.pos += 1; // single whitespace character between the header and the raster data
+        }
+
+        // the header can't be trusted; make sure the raster it describes could fit in the rest of the data
+        // before allocating room for it. plain formats take at least one character per value.
+        let sample_bytes = if maxval > 255 { 2 } else { 1 };
+        let pixel_count = width.checked_mul(height);
+        let min_raster_len = match magic.as_str() {
+            "P1" | "P2" => pixel_count,
+            "P3"        => pixel_count.and_then(|count| count.checked_mul(3)),
+            "P4"        => width.div_ceil(8).checked_mul(height),
+            "P5"        => pixel_count.and_then(|count| count.checked_mul(sample_bytes)),
+            _ /* P6 */  => pixel_count.and_then(|count| count.checked_mul(3 * sample_bytes)),
+        };
+        match (pixel_count, min_raster_len) {
+            (Some(_), Some(len)) if len <= data.len().saturating_sub(reader.pos) => {},
+            _ => return Err(Error::Logic(format!(
+                     "Image data is too short for a {}x{} image of type {}", width, height, magic))),
+        }
+
+        let scale = |value: usize| (value.min(maxval) * 255 / maxval) as u8;
+        let luminance = |r: u8, g: u8, b: u8| ((299 * r as usize + 587 * g as usize + 114 * b as usize) / 1000) as u8;
+        let mut pixels = Vec::with_capacity(width * height);
+        match magic.as_str() {
+            "P1" => for _ in 0..width * height {
+                pixels.push(if reader.bit()? { 0 } else { 255 }); // 1 is black
+            },
+            "P4" => for _ in 0..height {
+                let row = reader.bytes(width.div_ceil(8))?;
+                pixels.extend((0..width).map(|x| if row[x / 8] & (0x80 >> (x % 8)) != 0 { 0 } else { 255 }));
+            },
+            "P2" => for _ in 0..width * height {
+                pixels.push(scale(reader.number()?));
+            },
+            "P3" => for _ in 0..width * height {
+                let (r, g, b) = (scale(reader.number()?), scale(reader.number()?), scale(reader.number()?));
+                pixels.push(luminance(r, g, b));
+            },
+            "P5" => for _ in 0..width * height {
+                pixels.push(scale(reader.sample(maxval)?));
+            },
+            _ /* P6 */ => for _ in 0..width * height {
+                let (r, g, b) = (scale(reader.sample(maxval)?), scale(reader.sample(maxval)?), scale(reader.sample(maxval)?));
+                pixels.push(luminance(r, g, b));
+            },
+        }
+        Ok(GrayImage { width, height, pixels })
+    }
+}
+
+struct NetpbmReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+impl<'a> NetpbmReader<'a> {
+    fn _skip_whitespace_and_comments(&mut self) {
+        while self.pos < self.data.len() {
+            match self.data[self.pos] {
+                b'#' => while self.pos < self.data.len() && self.data[self.pos] != b'\n' { self.pos += 1; },
+                c if c.is_ascii_whitespace() => self.pos += 1,
+                _ => break,
+            }
+        }
+    }
+    fn _unexpected_end(&self) -> Error {
+        Error::Logic("Unexpected end of image data".to_string())
+    }
+    fn token(&mut self) -> Result<String, Error> {
+        self._skip_whitespace_and_comments();
+        let start = self.pos;
+        while self.pos < self.data.len() && !self.data[self.pos].is_ascii_whitespace() && self.data[self.pos] != b'#' {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(self._unexpected_end());
+        }
+        Ok(String::from_utf8_lossy(&self.data[start..self.pos]).to_string())
+    }
+    fn number(&mut self) -> Result<usize, Error> {
+        let token = self.token()?;
+        token.parse::<usize>().map_err(|_| Error::Logic(format!("Invalid number '{}' in image data", token)))
+    }
+    fn bit(&mut self) -> Result<bool, Error> {
+        // plain PBM bits don't need to be separated by whitespace
+        self._skip_whitespace_and_comments();
+        let bit = match self.data.get(self.pos) {
+            Some(b'0') => false,
+            Some(b'1') => true,
+            Some(&c)   => return Err(Error::Logic(format!("Invalid character '{}' in PBM image data", c as char))),
+            None       => return Err(self._unexpected_end()),
+        };
+        self.pos += 1;
+        Ok(bit)
+    }
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], Error> {
+        let data: &'a [u8] = self.data;
+        let bytes = data.get(self.pos..self.pos + count).ok_or_else(|| self._unexpected_end())?;
+        self.pos += count;
+        Ok(bytes)
+    }
+    fn sample(&mut self, maxval: usize) -> Result<usize, Error> {
+        // raw samples are one byte each, or two (big-endian) if the maximum value doesn't fit in one
+        match maxval {
+            0..=255 => Ok(self.bytes(1)?[0] as usize),
+            _       => { let b = self.bytes(2)?; Ok((b[0] as usize) << 8 | b[1] as usize) },
+        }
+    }
+}
+
+#[cfg(all(test, feature = "image"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_pbm() {
+        // 1 is black; bits may or may not be separated by whitespace
+        let image = GrayImage::from_netpbm(b"P1\n# comment\n3 2\n1 0 1\n011\n").unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        assert_eq!(image.pixels, vec![0, 255, 0, 255, 0, 0]);
+    }
+
+    #[test]
+    fn raw_pbm() {
+        // rows are padded to a whole number of bytes, most significant bit first
+        let image = GrayImage::from_netpbm(b"P4\n10 2\n\xA0\x40\x00\xC0").unwrap();
+        assert_eq!((image.width, image.height), (10, 2));
+        assert_eq!(&image.pixels[..10], &[0, 255, 0, 255, 255, 255, 255, 255, 255, 0]);
+        assert_eq!(&image.pixels[10..], &[255, 255, 255, 255, 255, 255, 255, 255, 0, 0]);
+    }
+
+    #[test]
+    fn raw_pgm() {
+        let image = GrayImage::from_netpbm(b"P5 2 2 255\n\x00\x40\x80\xff").unwrap();
+        assert_eq!(image.pixels, vec![0, 64, 128, 255]);
+
+        // samples are scaled to 0-255, and take two bytes each if the maximum value needs them
+        let image = GrayImage::from_netpbm(b"P5 2 1 15\n\x00\x0f").unwrap();
+        assert_eq!(image.pixels, vec![0, 255]);
+        let image = GrayImage::from_netpbm(b"P5 2 1 65535\n\x00\x00\xff\xff").unwrap();
+        assert_eq!(image.pixels, vec![0, 255]);
+    }
+
+    #[test]
+    fn oversized_headers_are_rejected() {
+        for data in &[&b"P5\n100000 100000\n255\n"[..], b"P6 4294967296 4294967296 65535\n", b"P4 9 2\n\x00\x00\x00",
+                      b"P1 2 2 1 0 1"] {
+            assert!(matches!(GrayImage::from_netpbm(data), Err(Error::Logic(_))), "{}", String::from_utf8_lossy(data));
+        }
+    }
+
+    #[test]
+    fn downscale_averages_blocks() {
+        let image = GrayImage { width: 4, height: 2, pixels: vec![0, 100, 200, 255,
+                                                                  50, 150, 255, 255] };
+        let scaled = image.downscale(2, 1);
+        assert_eq!((scaled.width, scaled.height), (2, 1));
+        assert_eq!(scaled.pixels, vec![75, 241]);
+        assert_eq!(image.downscale(4, 2).pixels, image.pixels);
+    }
+
+    #[test]
+    fn threshold_is_exclusive() {
+        // only pixels strictly darker than the threshold become filled in squares
+        let data = b"P5 3 1 255\n\x7f\x80\x81";
+        let filled = |threshold| Puzzle::_from_netpbm(data, threshold, None).unwrap().rows[0].runs().iter()
+                                                                            .map(|run| run.length).collect::<Vec<_>>();
+        assert_eq!(filled(128), vec![1]);
+        assert_eq!(filled(129), vec![2]);
+        assert_eq!(filled(0), Vec::<usize>::new());
+    }
+}
@@ -0,0 +1,18 @@
+// vim: set ai et ts=4 sts=4 sw=4:
+
+pub mod util;
+pub mod puzzle;
+pub mod grid;
+pub mod row;
+pub mod options;
+pub mod non;
+#[cfg(feature = "ui")]
+pub mod ui;
+#[cfg(feature = "image")]
+pub mod image;
+#[cfg(feature = "webpbn")]
+pub mod webpbn;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
@@ -1,29 +1,38 @@
 // vim: set ai et ts=4 sts=4 sw=4:
 #![allow(dead_code, unused_imports)]
 use std::fs;
+use std::sync::{Arc, Mutex};
 use std::mem;
-use std::io;
+use std::cmp::max;
+use std::io::{self, Read, BufRead, Write};
 use std::env;
 use std::ops::Range;
 use std::convert::TryFrom;
 use std::process::exit;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::collections::{HashSet, HashMap};
+use std::time::{Duration, Instant};
 use std::vec::Vec;
 use yaml_rust::{YamlLoader, Yaml};
 use clap::{Arg, App, ArgMatches};
 use fern;
 use log::{self, trace, debug, info, log_enabled, Level::Debug};
 
-mod util;
-mod puzzle;
-mod grid;
-mod row;
-mod ui;
+use nonogram::util::{is_a_tty, Direction, Direction::*};
+use nonogram::puzzle::{Puzzle, Solver, SpeculativeStep};
+use nonogram::row::{Row, DirectionalSequence, overlap_strategies};
+#[cfg(feature = "ui")]
+use nonogram::ui::ui_main;
+use nonogram::grid::{Change, StatusChange, RunChange, SquareStatus, Error};
+use nonogram::options::{SolveOptions, RenderOptions, QueueOrder};
 
-use self::util::{is_a_tty, Direction, Direction::*};
-use self::puzzle::{Puzzle, Solver};
-use self::row::{Row, DirectionalSequence};
-use self::ui::ui_main;
-use self::grid::{Change, StatusChange, RunChange, SquareStatus, Error};
+#[derive(Debug, PartialEq)]
+pub enum OutputMode {
+    Board,      // full board including run clues and borders
+    Solution,   // bare grid only, one character per square
+    Coords,     // full board and borders, with row and column indices instead of clues
+}
 
 #[derive(Debug)]
 pub struct Args {
@@ -31,122 +40,517 @@ pub struct Args {
     verbosity: u64,
     input_file: String,
     emit_color: bool,
-    visual_groups: Option<usize>,
+    visual_groups: (Option<usize>, Option<usize>), // row and col group sizes
+    output: OutputMode,
+    deadline: Option<Instant>,
+    logic_only: bool,
+    max_depth: Option<usize>, // maximum number of nested guesses, if limited
+    dump_placements: bool,
+    emit_goal: bool,
+    guess_first: SquareStatus, // status to try first when speculatively changing a square
+    index: usize, // which puzzle to solve when the input file contains several
+    batch: Option<String>, // directory of puzzles to solve in batch mode, if any
+    font: Option<String>, // path to the font file to use in the UI, instead of the built-in one
+    emit_stats: bool, // whether to print solve statistics as JSON after solving
+    resume: Option<String>, // file with a saved grid state to continue from, if any
+    compact: bool, // whether to print the board with one character per square
+    svg: Option<String>, // file to write an SVG rendering of the board to after solving, if any
+    repl: bool, // whether to read solver commands from stdin instead of solving right away
+    seed: Option<u64>, // seed for randomized guess selection, if any
+    trace_out: Option<Arc<Mutex<fs::File>>>, // file to write a JSON snapshot of the board to after every iteration, if any
+    print_every: usize, // print the board in the debug output every Nth iteration, or never if 0
+    explain: bool, // annotate each change in the debug output with the deduction that made it
+    queue_order: QueueOrder, // which lines the solver evaluates first, by direction
+    queue_priority: bool, // whether re-queued lines of the direction evaluated first go ahead of the other direction's
+    fill_overlap: bool, // whether to run the FillOverlap strategy as well
+}
+
+impl Args {
+    fn render_options(&self) -> RenderOptions {
+        RenderOptions {
+            subdivision: self.visual_groups,
+            emit_color: self.emit_color,
+            compact: self.compact,
+            coordinates: self.output == OutputMode::Coords,
+        }
+    }
+    fn solve_options(&self) -> SolveOptions {
+        SolveOptions {
+            guess_first: self.guess_first,
+            deadline: self.deadline,
+            logic_only: self.logic_only,
+            max_depth: self.max_depth,
+            seed: self.seed,
+            trace_out: self.trace_out.clone(),
+            render: self.render_options(),
+            print_every: self.print_every,
+            explain: self.explain,
+            queue_order: self.queue_order,
+            queue_priority: self.queue_priority,
+            fill_overlap: self.fill_overlap,
+            ..SolveOptions::default()
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SolveStats {
+    iterations: usize,       // total solver iterations across all levels of speculation
+    guesses: usize,          // number of speculative changes made
+    depth: usize,            // current level of speculation
+    max_depth: usize,        // deepest level of speculation reached
+    elapsed: Duration,       // wall clock time spent solving
+    completion_ratio: f64,   // fraction of squares known at the end
+    fill_density: f64,       // fraction of squares filled in in the solution, according to the clues
+    solved: bool,
+    unique_solution: bool,   // whether the solution is known to be the only one
+    strategy_changes: HashMap<&'static str, usize>, // number of changes made by each line solving strategy, across all levels
+}
+impl SolveStats {
+    pub fn finish(&mut self, puzzle: &Puzzle, elapsed: Duration) {
+        // fills in the final statistics once solving has ended, successfully or not.
+        // a solution reached by logic alone is necessarily unique; once guessing was involved,
+        // we stop at the first solution found, so uniqueness isn't confirmed.
+        self.elapsed = elapsed;
+        self.completion_ratio = puzzle.completion_ratio();
+        self.fill_density = puzzle.fill_density();
+        self.solved = puzzle.is_completed();
+        self.unique_solution = self.solved && self.guesses == 0;
+    }
+    pub fn to_json(&self) -> String {
+        let mut strategy_changes = self.strategy_changes.iter().collect::<Vec<_>>();
+        strategy_changes.sort();
+        format!("{{\"iterations\":{},\"guesses\":{},\"max_depth\":{},\"elapsed_ms\":{:.3},\"completion_ratio\":{:.4},\"fill_density\":{:.4},\"solved\":{},\"unique_solution_confirmed\":{},\"strategy_changes\":{{{}}}}}",
+            self.iterations, self.guesses, self.max_depth, self.elapsed.as_secs_f64() * 1000.0,
+            self.completion_ratio, self.fill_density, self.solved, self.unique_solution,
+            strategy_changes.iter().map(|(name, count)| format!("\"{}\":{}", name, count)).collect::<Vec<_>>().join(","))
+    }
+}
+
+fn solve_outcome(result: &Result<Puzzle, Box<(Error, Puzzle)>>, stats: &SolveStats) -> (i32, String)
+{
+    // exit code and one-line summary for the outcome of solving a puzzle, for use in scripts:
+    //  0: solved, and the solution is known to be unique
+    //  1: solved, but guessing was involved and uniqueness wasn't checked
+    //  2: unsolvable, the clues contradict each other (or the given squares)
+    //  3: stalled without a solution; logic alone got stuck with guessing disabled, or solving was cut short
+    //     (by a timeout, cancellation, the depth limit or the iteration limit)
+    match result {
+        Ok(_) if stats.solved && stats.unique_solution => (0, "solved; the solution is unique".to_string()),
+        Ok(_) if stats.solved => (1, "solved by guessing; uniqueness was not checked".to_string()),
+        Ok(_) => (3, format!("stalled at {:.1}% complete; no more logical deductions can be made", stats.completion_ratio * 100.0)),
+        Err(err) => match &err.0 {
+            e @ Error::Timeout | e @ Error::Cancelled | e @ Error::DepthLimit | e @ Error::IterationLimit =>
+                (3, format!("stalled at {:.1}% complete; {}", stats.completion_ratio * 100.0, e)),
+            e => (2, format!("unsolvable; {}", e)),
+        },
+    }
+}
+
+fn format_puzzle(puzzle: &Puzzle, args: &Args) -> String
+{
+    // formats the given puzzle for final output, according to the selected output mode
+    match args.output {
+        OutputMode::Board | OutputMode::Coords => puzzle._fmt(&args.render_options()),
+        OutputMode::Solution => puzzle.fmt_solution(),
+    }
+}
+
+fn write_svg(puzzle: &Puzzle, args: &Args)
+{
+    // writes the given puzzle to the SVG file given on the command line, if any
+    if let Some(path) = &args.svg {
+        if let Err(e) = fs::write(path, puzzle.render_svg(&args.render_options())) {
+            eprintln!("Failed to write SVG to {}: {}", path, e);
+        }
+    }
+}
+
+fn write_trace_frame(trace_out: &Mutex<fs::File>, iteration: usize, depth: usize, direction: Direction, index: usize, puzzle: &Puzzle)
+{
+    // appends a snapshot of the board after a solver iteration to the trace file, as one line of JSON.
+    // the grid is given row by row, with one character per square: '#' filled in, 'x' crossed out, '.' unknown.
+    let frame = format!("{{\"iteration\":{},\"depth\":{},\"direction\":\"{}\",\"index\":{},\"width\":{},\"height\":{},\"grid\":\"{}\"}}",
+        iteration, depth, direction, index, puzzle.width(), puzzle.height(), puzzle.ascii_state().replace('\n', ""));
+    if let Err(e) = writeln!(trace_out.lock().unwrap(), "{}", frame) {
+        eprintln!("Failed to write to trace file: {}", e);
+    }
 }
 
-fn _solve_with_logic(solver: &mut Solver, args: &Args) -> Result<(), Error>
+fn new_solver(puzzle: Puzzle, options: &SolveOptions) -> Solver
+{
+    // creates a solver for the given puzzle, set up according to the given options
+    let mut solver = Solver::new(puzzle);
+    solver.max_iterations = options.max_iterations;
+    solver.max_depth = options.max_depth;
+    solver.record_history = options.record_history;
+    if options.fill_overlap && !solver.puzzle.wrap {
+        solver.strategies = overlap_strategies();
+    }
+    solver.set_queue_order(options.queue_order);
+    solver.set_queue_priority(options.queue_priority);
+    if let Some(deadline) = options.deadline {
+        solver.set_deadline(deadline);
+    }
+    if let Some(seed) = options.seed {
+        solver.set_seed(seed);
+    }
+    solver
+}
+
+fn solve(puzzle: Puzzle, options: &SolveOptions, stats: &mut SolveStats)
+    -> Result<Puzzle, Box<(Error, Puzzle)>>
+{
+    // attempts to solve the given puzzle to completion.
+    // returns the solved puzzle on success, or an error indicator in case of an impossibility or a conflict
+    // (boxed, along with the puzzle as far as it got, to keep the Ok path small).
+    //
+    // whenever logic runs out (and guessing isn't disabled), the solver speculatively gives a square a status and
+    // carries on, retracting the guess if it leads to a conflict; see Solver::next_speculative. if solving gets cut
+    // short (by a timeout, cancellation, the depth limit or the iteration limit), that says nothing about any of the
+    // guesses in effect, so they're dropped and the puzzle is reported as it was before the first one.
+    let mut solver = new_solver(puzzle, options);
+    debug!("starting state:");
+    debug!("\n{}", solver.puzzle._fmt(&options.render));
+
+    let result = _solve_inner(&mut solver, options, stats);
+    stats.iterations += solver.iterations;
+    for (&name, &count) in solver.strategy_stats() {
+        *stats.strategy_changes.entry(name).or_insert(0) += count;
+    }
+    match result {
+        Ok(()) => Ok(solver.puzzle),
+        Err(e) => {
+            solver.abandon_guesses();
+            stats.depth = 0;
+            Err(Box::new((e, solver.puzzle)))
+        },
+    }
+}
+fn _solve_inner(solver: &mut Solver, options: &SolveOptions, stats: &mut SolveStats) -> Result<(), Error>
 {
-    // tries to solve the puzzle as far as possible using only logically-inferrable changes
+    // runs the solver until it's done, logging its progress and keeping track of the guesses it makes.
     // returns Ok(()) when there are no more actions (regardless of whether the puzzle has been solved),
-    // or Err(Error) in case a conflict or impossibility was found.
-    while let Some(iteration_result) = solver.next() {
-        match iteration_result {
-            Ok((row_dir, row_idx, changes)) => {
+    // or Err(Error) in case a conflict or impossibility was found that can't be blamed on a guess.
+    loop {
+        let step = match options.logic_only {
+            true  => solver.next().map(|result| result.map(|(d, i, changes)| SpeculativeStep::Iteration(d, i, changes))),
+            false => solver.next_speculative(options.guess_first),
+        };
+        match step {
+            None => break,
+            Some(Ok(SpeculativeStep::Iteration(row_dir, row_idx, changes))) => {
+                if let Some(trace_out) = &options.trace_out {
+                    write_trace_frame(trace_out, solver.iterations, stats.depth, row_dir, row_idx, &solver.puzzle);
+                }
                 if log_enabled!(Debug) {
                     debug!("finished solvers on {} row {}; changes in this iteration:", row_dir, row_idx);
-                    for change in &changes {
-                        debug!("  {}", change);
+                    if options.explain {
+                        for (change, reason) in changes.iter().zip(solver.last_reasons()) {
+                            debug!("  {}  [{}]", change, reason);
+                        }
+                    } else {
+                        for change in &changes {
+                            debug!("  {}", change);
+                        }
                     }
 
-                    debug!("\n{}", solver.puzzle._fmt(args.visual_groups, args.emit_color));
+                    // the final board is always printed once logic runs out, so skipping intermediate ones is fine
+                    if options.print_every > 0 && solver.iterations.is_multiple_of(options.print_every) {
+                        debug!("\n{}", solver.puzzle._fmt(&options.render));
+                    }
                     debug!("--------------------------------------");
                     debug!("");
                 }
             },
-            Err(e) => {
+            Some(Ok(SpeculativeStep::Probed(changes))) => {
+                debug!("puzzle partially solved, out of actions ({} iterations).", solver.iterations);
+                debug!("probing found {} changes, resuming logic solving", changes.len());
+            },
+            Some(Ok(SpeculativeStep::Excluded(guesses))) => {
+                for guess in guesses {
+                    debug!("guess {} is known to fail; square must be the inverse instead", guess);
+                }
+            },
+            Some(Ok(SpeculativeStep::Guessed(guess))) => {
+                // decided that it's gonna be a square of the preferred guess status; see if anything freaks out
+                debug!("puzzle partially solved, out of actions ({} iterations).", solver.iterations);
+                info!("guess: setting square (x={}, y={}) to {}", guess.x, guess.y, guess.status);
+                stats.guesses += 1;
+                stats.depth = solver.guesses().count();
+                stats.max_depth = max(stats.max_depth, stats.depth);
+                debug!("\n{}", solver.puzzle._fmt(&options.render));
+            },
+            Some(Ok(SpeculativeStep::Retracted(guess))) => {
+                info!("guess (x={}, y={}) -> {} produced an error; must be the inverse instead", guess.x, guess.y, guess.status);
+                stats.depth = solver.guesses().count();
+            },
+            Some(Err(e)) => {
                 debug!("\nencountered error during solving:");
                 debug!("{}", e);
                 return Err(e);
-            }
+            },
+        }
+    }
+
+    debug!("final state:");
+    debug!("\n{}", solver.puzzle._fmt(&options.render));
+    if solver.puzzle.is_completed() {
+        debug!("puzzle solved! ({} iterations)", solver.iterations);
+        for guess in solver.guesses() {
+            info!("guess {} panned out", guess);
+        }
+        if options.logic_only {
+            info!("puzzle fully solved by logic alone");
         }
+    } else {
+        let num_unknown = solver.puzzle.width() * solver.puzzle.height() - solver.puzzle.num_known_squares();
+        info!("puzzle stalled without speculation; {} squares remain unknown ({} iterations)", num_unknown, solver.iterations);
     }
-    return Ok(())
+    Ok(())
 }
 
-fn solve(puzzle: Puzzle, args: &Args) -> Result<Puzzle, (Error, Puzzle)>
+fn parse_puzzles(path: &Path, contents: &str) -> Result<Vec<Puzzle>, Error>
 {
-    // attempts to solve the given puzzle to completion.
-    // returns the solved puzzle on success, or an error indicator in case of an impossibility or a conflict.
+    // parses the puzzle(s) in the contents of the given file; .non files and webpbn XML exports hold one
+    // puzzle each, anything else is taken to be YAML.
+    if path.extension().and_then(|ext| ext.to_str()) == Some("non") {
+        return Puzzle::from_non(contents).map(|puzzle| vec![puzzle]);
+    }
+    #[cfg(feature = "webpbn")]
+    {
+        if path.extension().and_then(|ext| ext.to_str()) == Some("xml") {
+            return Puzzle::from_webpbn_xml(contents).map(|puzzle| vec![puzzle]);
+        }
+    }
+    Puzzle::all_from_yaml(contents)
+}
 
-    let mut solver = Solver::new(puzzle);
-    //let mut speculation_bases = Vec::<Puzzle>::new();
+fn solve_batch(dir: &str, args: &Args)
+{
+    // solves every puzzle file (YAML, .non, or webpbn XML if supported) in the given directory, and prints a summary table of the results.
+    // failures to read or solve individual files (including panics) are reported at the end rather than aborting the run.
+    // files in other formats are skipped, and counted in the summary.
+    let (mut paths, skipped): (Vec<_>, Vec<_>) = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok())
+                              .map(|entry| entry.path())
+                              .filter(|path| path.is_file())
+                              .partition(|path| matches!(path.extension().and_then(|ext| ext.to_str()), Some("yml") | Some("yaml") | Some("non"))
+                                                || (cfg!(feature = "webpbn") && path.extension().and_then(|ext| ext.to_str()) == Some("xml"))),
+        Err(e) => { eprintln!("Failed to read directory {}: {}", dir, e); exit(1); },
+    };
+    paths.sort();
 
-    // keep a queue of rows to be looked at, and run the individual solvers on each
-    // of them in sequence until there are none left in the queue. whenever a change
-    // is made to a square in the grid, those rows are added back into the queue
-    // for evaluation on the next run. completed runs are removed from the queue.
-    debug!("starting state:");
-    debug!("\n{}", solver.puzzle._fmt(args.visual_groups, args.emit_color));
+    // progress goes to stderr so that it doesn't end up in the summary table; on a terminal it's a single line
+    // that gets overwritten as we go (and cleared before each table row), otherwise a plain line per finished file.
+    let interactive = is_a_tty(io::stderr());
+    let batch_start = Instant::now();
 
-    loop
-    {
-        if let Err(e) = _solve_with_logic(&mut solver, args) {
-            return Err((e, solver.puzzle));
-        }
-
-        debug!("final state:");
-        debug!("\n{}", solver.puzzle._fmt(args.visual_groups, args.emit_color));
-
-        if solver.puzzle.is_completed() {
-            debug!("puzzle solved! ({} iterations)", solver.iterations);
-            break;
-        }
-
-        debug!("puzzle partially solved, out of actions ({} iterations).", solver.iterations);
-
-        // we're out of decisions that can be made with logic, so we're forced to start solving
-        // speculatively -- i.e. make a decision at some point and see if it introduces a logic error;
-        // if it does, revert the work and make the opposite change.
-        let edited_puzzle = solver.puzzle.clone();
-
-        // find a square with unknown state and set it to something, and try to continue
-        // TODO: how to choose a square to speculatively change, and do we make it filled in or crossed out?
-        // can we come up with some metric of "further solving power" resulting from changing a square's state?
-        // TODO: besides setting a square's state, we could also pick one that's filled in but doesn't have a known
-        // run, and update the run and see what happens; that might actually give pretty good solving power ...
-        let mut unknown_square: Option<(usize, usize)> = None;
-        let incomplete_rows = edited_puzzle.incomplete_rows();
-        for (d,i) in incomplete_rows {
-            let row: &Row = solver.puzzle.get_row(d,i);
-            if let Some(sq) = (0..row.length).map(|at| row.get_square(at))
-                                             .filter(|sq| sq.get_status() == SquareStatus::Unknown)
-                                             .next() {
-                unknown_square = Some((sq.get_col(), sq.get_row()));
-                break;
-            }
+    let mut failures = Vec::<(String, String)>::new();
+    println!("{:<40} {:<8} {:>10} {:>10} {:>8}", "file", "result", "iterations", "time (ms)", "guessed");
+    for (done, path) in paths.iter().enumerate() {
+        let filename = path.file_name().unwrap().to_string_lossy().to_string();
+        if interactive {
+            eprint!("\r\x1b[K{}", batch_progress(done, paths.len(), &filename, batch_start.elapsed()));
+            io::stderr().flush().unwrap();
+        }
+        let puzzle = match fs::read_to_string(path).map_err(|e| e.to_string())
+                                                   .and_then(|contents| catch_panic(|| parse_puzzles(path, &contents))
+                                                                           .and_then(|result| result.map_err(|e| e.to_string())))
+        {
+            Ok(mut puzzles) if args.index < puzzles.len() => puzzles.swap_remove(args.index),
+            Ok(_)  => { failures.push((filename, format!("no puzzle at index {}", args.index))); continue; },
+            Err(e) => { failures.push((filename, e)); continue; },
+        };
+
+        let mut stats = SolveStats::default();
+        let start = Instant::now();
+        let result = catch_panic(|| solve(puzzle, &args.solve_options(), &mut stats)
+                                        .map(|puzzle| puzzle.is_completed())
+                                        .map_err(|err| err.0.to_string()));
+        let elapsed = start.elapsed();
+        let solved = match result.and_then(|result| result) {
+            Ok(solved) => solved,
+            Err(e)     => { failures.push((filename.clone(), e)); false },
+        };
+        if interactive {
+            eprint!("\r\x1b[K");
         }
+        println!("{:<40} {:<8} {:>10} {:>10.1} {:>8}",
+            filename,
+            if solved { "solved" } else { "unsolved" },
+            stats.iterations,
+            elapsed.as_secs_f64() * 1000.0,
+            if stats.guesses > 0 { "yes" } else { "no" });
+        if !interactive {
+            eprintln!("{}", batch_progress(done+1, paths.len(), &filename, batch_start.elapsed()));
+        }
+    }
 
-        // decide that it's gonna be a filled in square and see if anything freaks out
-        let (x,y) = unknown_square.unwrap(); // has to succeed, otherwise the puzzle would've been solved
-        debug!("speculatively change: setting square (x={}, y={}) to {}", x, y, SquareStatus::FilledIn);
-        edited_puzzle.get_square_mut(x,y).set_status(SquareStatus::FilledIn).unwrap();
+    if !failures.is_empty() {
+        println!();
+        println!("{} of {} puzzles failed:", failures.len(), paths.len());
+        for (filename, reason) in &failures {
+            println!("  {}: {}", filename, reason);
+        }
+    }
+    if !skipped.is_empty() {
+        println!();
+        println!("skipped {} file(s) in unsupported formats (only .yml/.yaml/.non{} files are solved)",
+            skipped.len(), if cfg!(feature = "webpbn") { " and .xml" } else { "" });
+    }
+}
 
-        // recursively try to solve with the given speculative change; in case of a conflict, make the inverse
-        // change and continue.
-        match solve(edited_puzzle, args) {
-            Ok(solved_puzzle) =>  {
-                // we made the right edit, and the recursive call managed to finish solving the whole puzzle,
-                // so we can just make that our current one and break out of the solve loop
-                solver.puzzle = solved_puzzle;
-                break;
+fn catch_panic<T, F: FnOnce() -> T>(f: F) -> Result<T, String>
+{
+    // runs the given closure, turning a panic inside of it into an error message, so that a bug triggered by
+    // one puzzle doesn't bring down a whole batch
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+                             .or_else(|| payload.downcast_ref::<String>().cloned())
+                             .unwrap_or_else(|| "unknown cause".to_string());
+        format!("panicked: {}", message)
+    })
+}
+
+fn batch_progress(done: usize, total: usize, filename: &str, elapsed: Duration) -> String
+{
+    // e.g. "[3/10] 10x10-anchor.yml, 1.2s elapsed, ETA 2.8s"; the ETA assumes the remaining files take
+    // as long on average as the ones done so far, so there is none until the first file is done.
+    let mut result = format!("[{}/{}] {}, {:.1}s elapsed", done, total, filename, elapsed.as_secs_f64());
+    if done > 0 {
+        let eta = elapsed.as_secs_f64() / (done as f64) * ((total - done) as f64);
+        result.push_str(&format!(", ETA {:.1}s", eta));
+    }
+    result
+}
+
+const REPL_HELP: &str = "\
+commands:
+  step                       run a single solver iteration
+  run <n>                    run up to n solver iterations
+  print                      print the board
+  dump                       print the internal solver state
+  set <row>,<col> filled|crossed
+                             set the status of a square
+  hint                       describe the next logical deduction, without applying it
+  solve                      solve the rest of the puzzle, guessing if necessary
+  help                       show this list
+  quit                       exit";
+
+fn repl(puzzle: Puzzle, args: &Args)
+{
+    // reads commands from stdin and runs them against a solver for the given puzzle, printing the board
+    // after each one that can change it; see REPL_HELP for the list of commands.
+    let options = args.solve_options();
+    let mut solver = Solver::new(puzzle);
+    solver.max_iterations = options.max_iterations;
+    if options.fill_overlap && !solver.puzzle.wrap {
+        solver.strategies = overlap_strategies();
+    }
+    solver.set_queue_order(options.queue_order);
+    solver.set_queue_priority(options.queue_priority);
+    solver.strict = true; // report bad 'set' commands right away
+
+    println!("{}", solver.puzzle._fmt(&options.render));
+    println!("type 'help' for a list of commands");
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("> ");
+        io::stdout().flush().unwrap();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _              => break, // end of input
+        };
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words.as_slice() {
+            [] => continue,
+            ["help"] => { println!("{}", REPL_HELP); continue; },
+            ["quit"] | ["exit"] => break,
+            ["print"] => {},
+            ["dump"] => { println!("{}", solver.puzzle.dump_state()); continue; },
+            ["hint"] => {
+                match solver.hint() {
+                    Some(hint) => println!("{} -> {}: {}", hint.squares.iter().map(|(x, y)| format!("({},{})", y, x))
+                                                                   .collect::<Vec<_>>().join(" "),
+                                                         hint.status, hint.reason),
+                    None       => println!("no logical deduction available"),
+                }
+                continue;
+            },
+            ["step"] | ["run", _] => {
+                let n = match words.as_slice() {
+                    ["run", n] => match n.parse::<usize>() {
+                        Ok(n)  => n,
+                        Err(_) => { println!("invalid number of iterations: {}", n); continue; },
+                    },
+                    _ => 1,
+                };
+                for _ in 0..n {
+                    match solver.next() {
+                        Some(Ok((d, i, changes))) => println!("{} row {}: {} changes", d, i, changes.len()),
+                        Some(Err(e))              => { println!("error: {}", e); break; },
+                        None                      => { println!("out of logical deductions"); break; },
+                    }
+                }
             },
-            Err(_) => {
-                // we made the wrong edit; apply the inverse change and continue trying to solve it
-                debug!("speculative change (x={}, y={}) -> {} produced an error", x, y, SquareStatus::FilledIn);
-                debug!("must therefore be {} instead, making that change", SquareStatus::CrossedOut);
-                solver.puzzle.get_square_mut(x,y).set_status(SquareStatus::CrossedOut).unwrap();
+            ["set", pos, status] => {
+                let coords = pos.split(',').map(|c| c.parse::<usize>()).collect::<Vec<_>>();
+                let (row, col) = match coords.as_slice() {
+                    [Ok(row), Ok(col)] if *row < solver.puzzle.height() && *col < solver.puzzle.width() => (*row, *col),
+                    _ => { println!("invalid square: {} (expected <row>,<col> within the grid)", pos); continue; },
+                };
+                let status = match *status {
+                    "filled"  => SquareStatus::FilledIn,
+                    "crossed" => SquareStatus::CrossedOut,
+                    _         => { println!("invalid status: {} (expected filled or crossed)", status); continue; },
+                };
+                if let Err(e) = solver.set_square_status(col, row, status) {
+                    println!("error: {}", e);
+                }
+            },
+            ["solve"] => {
+                let mut stats = SolveStats::default();
+                match solve(solver.puzzle.clone(), &options, &mut stats) {
+                    Ok(solved)  => solver = Solver::new(solved),
+                    Err(err)    => println!("error: {}", err.0),
+                }
             },
+            _ => { println!("unknown command: {} (type 'help' for a list)", line.trim()); continue; },
         }
+        println!("{}", solver.puzzle._fmt(&options.render));
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(name: &str, value: &str) -> T
+{
+    // parses the value of a numeric command-line argument, exiting with a message instead of panicking
+    match value.parse::<T>() {
+        Ok(parsed) => parsed,
+        Err(_)     => { eprintln!("Invalid value for --{}: {}", name, value); exit(1); },
     }
-    Ok(solver.puzzle)
 }
 
+fn parse_deadline(value: &str) -> Instant
+{
+    // the timeout has to be a finite, non-negative number of seconds that doesn't overflow the clock
+    let secs = parse_arg::<f64>("timeout", value);
+    if !secs.is_finite() || secs < 0.0 {
+        eprintln!("Invalid value for --timeout: {} (expected a non-negative number of seconds)", value);
+        exit(1);
+    }
+    match Duration::try_from_secs_f64(secs).ok().and_then(|timeout| Instant::now().checked_add(timeout)) {
+        Some(deadline) => deadline,
+        None           => { eprintln!("Invalid value for --timeout: {} (too large)", value); exit(1); },
+    }
+}
 
 fn main() {
     let args = App::new("nonogram")
                    .arg(Arg::with_name("input_file")
-                             .required(true)
-                             .help("input YAML file containing the puzzle definition")
+                             .required(false)
+                             .help("input YAML (or .non) file containing the puzzle definition; use - (or omit when piping) to read from stdin")
                              .index(1))
                    .arg(Arg::with_name("color")
                              .help("whether to output ANSI color escape sequences")
@@ -157,13 +561,141 @@ fn main() {
                    .arg(Arg::with_name("ui")
                              .long("ui")
                              .takes_value(false))
+                   .arg(Arg::with_name("repl")
+                             .help("read commands from stdin to step through and query the solver (type 'help' for a list)")
+                             .long("repl")
+                             .takes_value(false)
+                             .conflicts_with("ui"))
+                   .arg(Arg::with_name("compact")
+                             .help("print the board with one character per square and without subdivision lines")
+                             .long("compact")
+                             .takes_value(false))
+                   .arg(Arg::with_name("resume")
+                             .help("continue from a grid state saved from the UI (with Ctrl+S)")
+                             .long("resume")
+                             .takes_value(true)
+                             .required(false))
+                   .arg(Arg::with_name("font")
+                             .help("path to a TTF font file to use in the UI (defaults to the built-in Fira Sans)")
+                             .long("font")
+                             .takes_value(true)
+                             .required(false))
                    .arg(Arg::with_name("groups")
-                             .help("row group sizes when outputting puzzle visually")
+                             .help("row and column group sizes when outputting puzzle visually")
                              .short("g")
                              .long("groups")
                              .takes_value(true)
                              .required(false)
                              .default_value("5"))
+                   .arg(Arg::with_name("groups_rows")
+                             .help("row group sizes when outputting puzzle visually; overrides --groups for rows")
+                             .long("groups-rows")
+                             .takes_value(true)
+                             .required(false))
+                   .arg(Arg::with_name("groups_cols")
+                             .help("column group sizes when outputting puzzle visually; overrides --groups for columns")
+                             .long("groups-cols")
+                             .takes_value(true)
+                             .required(false))
+                   .arg(Arg::with_name("output")
+                             .help("what to print once solving finishes: the full board, only the solution grid, or the board with row and column indices instead of clues")
+                             .short("o")
+                             .long("output")
+                             .takes_value(true)
+                             .required(false)
+                             .possible_values(&["board", "solution", "coords"])
+                             .default_value("board"))
+                   .arg(Arg::with_name("timeout")
+                             .help("give up solving after this many seconds")
+                             .short("t")
+                             .long("timeout")
+                             .takes_value(true)
+                             .required(false))
+                   .arg(Arg::with_name("logic_only")
+                             .help("stop once no more logical deductions can be made instead of guessing")
+                             .long("logic-only")
+                             .takes_value(false))
+                   .arg(Arg::with_name("max_depth")
+                             .help("give up instead of nesting guesses more than this many levels deep")
+                             .long("max-depth")
+                             .takes_value(true)
+                             .required(false))
+                   .arg(Arg::with_name("dump")
+                             .help("additionally dump internal solver state after solving")
+                             .long("dump")
+                             .takes_value(true)
+                             .required(false)
+                             .possible_values(&["placements"]))
+                   .arg(Arg::with_name("stats")
+                             .help("print statistics about the solve in the given format after solving")
+                             .long("stats")
+                             .takes_value(true)
+                             .required(false)
+                             .possible_values(&["json"]))
+                   .arg(Arg::with_name("svg")
+                             .help("write the board to the given file as an SVG image after solving")
+                             .long("svg")
+                             .takes_value(true)
+                             .required(false))
+                   .arg(Arg::with_name("emit_goal")
+                             .help("print the solution as a .non format goal line after solving")
+                             .long("emit-goal")
+                             .takes_value(false))
+                   .arg(Arg::with_name("guess_first")
+                             .help("which status to try first when having to guess a square's status")
+                             .long("guess-first")
+                             .takes_value(true)
+                             .required(false)
+                             .possible_values(&["filled", "crossed"])
+                             .default_value("filled"))
+                   .arg(Arg::with_name("queue_order")
+                             .help("which lines the solver evaluates first: all rows or all columns before the other, or rows and columns in turn")
+                             .long("queue-order")
+                             .takes_value(true)
+                             .required(false)
+                             .possible_values(&["rows-first", "cols-first", "interleaved"])
+                             .default_value("rows-first"))
+                   .arg(Arg::with_name("queue_priority")
+                             .help("with --queue-order rows-first or cols-first, also re-queue lines in that direction ahead of the other direction's, rather than at the back")
+                             .long("queue-priority")
+                             .takes_value(false))
+                   .arg(Arg::with_name("fill_overlap")
+                             .help("also fill in the overlap between each run's leftmost and rightmost placement directly, ahead of the other deductions \
+                                    (off by default: on the bundled puzzles it saves about 0.1% of the iterations, and no time)")
+                             .long("fill-overlap")
+                             .takes_value(false))
+                   .arg(Arg::with_name("seed")
+                             .help("pick randomly among the most promising squares when guessing, reproducibly for the same seed")
+                             .long("seed")
+                             .takes_value(true)
+                             .required(false))
+                   .arg(Arg::with_name("trace_out")
+                             .help("write a JSON snapshot of the board to the given file after every solver iteration, one per line")
+                             .long("trace-out")
+                             .takes_value(true)
+                             .required(false)
+                             .conflicts_with("batch"))
+                   .arg(Arg::with_name("print_every")
+                             .help("with -v, print the board only every N solver iterations instead of after each one (0 to only print it at the end)")
+                             .long("print-every")
+                             .takes_value(true)
+                             .required(false)
+                             .default_value("1"))
+                   .arg(Arg::with_name("explain")
+                             .help("print the solver's changes along with the deduction behind each of them (implies -v)")
+                             .long("explain")
+                             .required(false))
+                   .arg(Arg::with_name("index")
+                             .help("which puzzle to solve if the input file contains several YAML documents (0-based)")
+                             .long("index")
+                             .takes_value(true)
+                             .required(false)
+                             .default_value("0"))
+                   .arg(Arg::with_name("batch")
+                             .help("solve all puzzle files (YAML, .non, or webpbn XML when built with the webpbn feature) in the given directory and print a summary")
+                             .long("batch")
+                             .takes_value(true)
+                             .required(false))
                    .arg(Arg::with_name("verbose")
                              .help("Increases logging verbosity each use for up to 3 times")
                              .short("v")
@@ -173,18 +705,59 @@ fn main() {
 
     let args: Args = Args {
         ui: args.is_present("ui"),
-        verbosity: args.occurrences_of("verbose"),
-        input_file: args.value_of("input_file").unwrap().to_string(),
+        verbosity: args.occurrences_of("verbose").max(args.is_present("explain") as u64),
+        input_file: args.value_of("input_file").unwrap_or("-").to_string(),
         emit_color: match args.value_of("color") {
             Some("yes")  => true,
             Some("no")   => false,
             _ => is_a_tty(io::stdout()),
         },
-        visual_groups: match args.value_of("groups") {
-            Some("0")    => None,
-            Some(x)      => Some(x.parse::<usize>().unwrap_or(5usize)),
-            None         => Some(5usize),
+        visual_groups: {
+            let groups = |value: Option<&str>| match value {
+                Some("0")    => None,
+                Some(x)      => Some(x.parse::<usize>().unwrap_or(5usize)),
+                None         => Some(5usize),
+            };
+            let both = args.value_of("groups");
+            (groups(args.value_of("groups_rows").or(both)),
+             groups(args.value_of("groups_cols").or(both)))
+        },
+        output: match args.value_of("output") {
+            Some("solution") => OutputMode::Solution,
+            Some("coords")   => OutputMode::Coords,
+            _                => OutputMode::Board,
+        },
+        deadline: args.value_of("timeout").map(parse_deadline),
+        logic_only: args.is_present("logic_only"),
+        max_depth: args.value_of("max_depth").map(|depth| parse_arg("max-depth", depth)),
+        dump_placements: args.value_of("dump") == Some("placements"),
+        emit_goal: args.is_present("emit_goal"),
+        guess_first: match args.value_of("guess_first") {
+            Some("crossed") => SquareStatus::CrossedOut,
+            _               => SquareStatus::FilledIn,
+        },
+        index: parse_arg("index", args.value_of("index").unwrap()),
+        batch: args.value_of("batch").map(String::from),
+        font: args.value_of("font").map(String::from),
+        emit_stats: args.value_of("stats") == Some("json"),
+        resume: args.value_of("resume").map(String::from),
+        compact: args.is_present("compact"),
+        svg: args.value_of("svg").map(String::from),
+        repl: args.is_present("repl"),
+        seed: args.value_of("seed").map(|seed| parse_arg("seed", seed)),
+        trace_out: args.value_of("trace_out").map(|path| match fs::File::create(path) {
+            Ok(file) => Arc::new(Mutex::new(file)),
+            Err(e)   => { eprintln!("Failed to create trace file {}: {}", path, e); exit(1); },
+        }),
+        print_every: parse_arg("print-every", args.value_of("print_every").unwrap()),
+        explain: args.is_present("explain"),
+        queue_order: match args.value_of("queue_order") {
+            Some("cols-first")  => QueueOrder::ColsFirst,
+            Some("interleaved") => QueueOrder::Interleaved,
+            _                   => QueueOrder::RowsFirst,
         },
+        queue_priority: args.is_present("queue_priority"),
+        fill_overlap: args.is_present("fill_overlap"),
     };
 
     let mut log_config = fern::Dispatch::new()
@@ -193,32 +766,113 @@ fn main() {
                             })
                             .chain(io::stdout());
     log_config = match args.verbosity {
+        0 if args.batch.is_some() => log_config.level(log::LevelFilter::Warn), // keep the summary table readable
         0 => log_config.level(log::LevelFilter::Info),
         1 => log_config.level(log::LevelFilter::Debug),
         _ => log_config.level(log::LevelFilter::Trace),
     };
     log_config.apply().unwrap();
 
-    let contents = fs::read_to_string(&args.input_file)
-                       .expect("Failed to read input file");
+    if let Some(dir) = &args.batch {
+        solve_batch(dir, &args);
+        return;
+    }
 
-    // note: column numbers are listed top to bottom
-    let docs: Vec<Yaml> = YamlLoader::load_from_str(&contents).unwrap();
-    let doc: &Yaml = &docs[0];
+    let contents = match args.input_file.as_str() {
+        "-" => {
+            // reading from stdin; refuse to block waiting on an interactive terminal
+            if is_a_tty(io::stdin()) {
+                eprintln!("No input file given, and stdin is a terminal; pass a puzzle file or pipe one in.");
+                exit(1);
+            }
+            let mut buf = String::new();
+            io::stdin().read_to_string(&mut buf)
+                       .expect("Failed to read puzzle from stdin");
+            buf
+        },
+        path => fs::read_to_string(path)
+                   .expect("Failed to read input file"),
+    };
 
-    let puzzle = Puzzle::from_yaml(doc);
-    if args.ui {
-        ui_main(puzzle, &args);
+    // note: column numbers are listed top to bottom
+    let mut puzzles = match parse_puzzles(Path::new(&args.input_file), &contents) {
+        Ok(puzzles) => puzzles,
+        Err(e)      => { eprintln!("{}", e); exit(1); },
+    };
+    if args.index >= puzzles.len() {
+        eprintln!("Puzzle index {} out of range, input contains {} puzzle(s)", args.index, puzzles.len());
+        exit(1);
+    }
+    let mut puzzle = puzzles.swap_remove(args.index);
+    if let Some(resume_file) = &args.resume {
+        let state = match fs::read_to_string(resume_file) {
+            Ok(state) => state,
+            Err(e)    => { eprintln!("Failed to read saved state {}: {}", resume_file, e); exit(1); },
+        };
+        if let Err(e) = puzzle.apply_ascii_state(&state) {
+            eprintln!("Failed to apply saved state {}: {}", resume_file, e);
+            exit(1);
+        }
+    }
+    if args.repl {
+        if args.input_file == "-" {
+            eprintln!("The REPL reads commands from stdin, so the puzzle has to be given as a file.");
+            exit(1);
+        }
+        repl(puzzle, &args);
+    } else if args.ui {
+        #[cfg(feature = "ui")]
+        {
+            // grid state is saved next to the input file, or in the working directory when reading from stdin
+            let state_path = match args.input_file.as_str() {
+                "-"  => "puzzle.state".to_string(),
+                path => format!("{}.state", path),
+            };
+            ui_main(puzzle, &args.render_options(), args.font.as_deref(), Path::new(&state_path));
+        }
+        #[cfg(not(feature = "ui"))]
+        {
+            eprintln!("This build of nonogram was compiled without UI support.");
+            exit(1);
+        }
     } else {
-        match solve(puzzle, &args) {
+        let mut stats = SolveStats::default();
+        let start = Instant::now();
+        let result = solve(puzzle, &args.solve_options(), &mut stats);
+        match &result {
+            Ok(puzzle) => stats.finish(puzzle, start.elapsed()),
+            Err(err)   => stats.finish(&err.1, start.elapsed()),
+        }
+        let (exit_code, summary) = solve_outcome(&result, &stats);
+        match result {
             Ok(solved) => {
-                println!("{}", solved._fmt(args.visual_groups, args.emit_color));
+                println!("{}", format_puzzle(&solved, &args));
+                write_svg(&solved, &args);
+                if args.emit_goal {
+                    match solved.goal_string() {
+                        Some(goal) => println!("goal \"{}\"", goal),
+                        None       => println!("puzzle was not fully solved, no goal line available"),
+                    }
+                }
+                if args.dump_placements {
+                    println!("{}", solved.placements_json());
+                }
             },
-            Err((e, partially_solved)) => {
-                println!("{}", partially_solved._fmt(args.visual_groups, args.emit_color));
+            Err(err) => {
+                let (e, partially_solved) = *err;
+                println!("{}", format_puzzle(&partially_solved, &args));
+                write_svg(&partially_solved, &args);
                 println!("encountered error during solving: {}", e);
                 debug!("{}", partially_solved.dump_state());
+                if args.dump_placements {
+                    println!("{}", partially_solved.placements_json());
+                }
             },
         }
+        if args.emit_stats {
+            println!("{}", stats.to_json());
+        }
+        eprintln!("{}", summary);
+        exit(exit_code);
     }
 }
@@ -0,0 +1,111 @@
+// vim: set ai et ts=4 sts=4 sw=4:
+use super::puzzle::Puzzle;
+use super::grid::Error;
+
+/// Support for reading puzzles in the `.non` format, as used by Simon Tatham's and Steve Simpson's solvers.
+///
+/// A `.non` file consists of keyword lines. The clues follow a `rows` and a `columns` line, one line of
+/// comma-separated run lengths per row or column, with `0` or an empty line for one without any runs.
+/// A blank line or the next keyword ends the list, unless `height` or `width` have been given, in which case
+/// exactly that many lines are read. Everything else (`title`, `by`, `goal`, ...) is skipped over.
+impl Puzzle {
+    pub fn from_non(text: &str) -> Result<Puzzle, Error> {
+        let mut width: Option<usize> = None;
+        let mut height: Option<usize> = None;
+        let mut row_clues: Option<Vec<Vec<usize>>> = None;
+        let mut col_clues: Option<Vec<Vec<usize>>> = None;
+
+        let mut lines = text.lines().map(str::trim).enumerate().peekable();
+        while let Some((line_nr, line)) = lines.next() {
+            let (keyword, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let dimension = || value.trim().parse::<usize>().map_err(|_| Error::Logic(format!(
+                "Invalid {} {:?} on line {} of .non file", keyword, value.trim(), line_nr + 1)));
+            match keyword {
+                "width"  => width = Some(dimension()?),
+                "height" => height = Some(dimension()?),
+                "rows" | "columns" => {
+                    let count = if keyword == "rows" { height } else { width };
+                    let mut clues = Vec::<Vec<usize>>::new();
+                    while count.is_none_or(|count| clues.len() < count) {
+                        match lines.peek() {
+                            Some(&(_, line)) if Self::_is_non_clue_line(line) || (line.is_empty() && count.is_some()) => {},
+                            _ => break,
+                        }
+                        let (line_nr, line) = lines.next().unwrap();
+                        clues.push(Self::_parse_non_clue_line(line).map_err(|_| Error::Logic(format!(
+                            "Invalid clues {:?} on line {} of .non file", line, line_nr + 1)))?);
+                    }
+                    if let Some(count) = count.filter(|&count| clues.len() < count) {
+                        return Err(Error::Logic(format!(
+                            ".non file lists only {} of its {} {}", clues.len(), count, keyword)));
+                    }
+                    match keyword {
+                        "rows" => row_clues = Some(clues),
+                        _      => col_clues = Some(clues),
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        match (row_clues, col_clues) {
+            (Some(row_clues), Some(col_clues)) => Puzzle::from_clues(row_clues, col_clues),
+            (None, _) => Err(Error::Logic(".non file contains no row clues".to_string())),
+            (_, None) => Err(Error::Logic(".non file contains no column clues".to_string())),
+        }
+    }
+    fn _is_non_clue_line(line: &str) -> bool {
+        // clue lines hold nothing but digits, commas and spaces; a blank line ends the list of clues
+        !line.is_empty() && line.chars().all(|c| c.is_ascii_digit() || c == ',' || c == ' ')
+    }
+    fn _parse_non_clue_line(line: &str) -> Result<Vec<usize>, std::num::ParseIntError> {
+        line.split([',', ' '])
+            .filter(|token| !token.is_empty())
+            .map(str::parse::<usize>)
+            .filter(|length| length != &Ok(0))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::row::Row;
+
+    fn clues(puzzle: &Puzzle) -> (Vec<Vec<usize>>, Vec<Vec<usize>>) {
+        // the run lengths of the puzzle's rows and columns
+        let lengths = |rows: &[Row]| rows.iter().map(|row| row.runs().iter().map(|run| run.length).collect())
+                                         .collect::<Vec<Vec<usize>>>();
+        (lengths(&puzzle.rows), lengths(&puzzle.cols))
+    }
+
+    #[test]
+    fn reads_clues_and_skips_other_keywords() {
+        let puzzle = Puzzle::from_non("catalogue \"test\"\ntitle \"Corner\"\nby \"someone\"\nwidth 3\nheight 3\n\n\
+                                       rows\n2\n1\n3\n\ncolumns\n1,1\n3\n1\n\ngoal \"110010111\"\n").unwrap();
+        assert_eq!(clues(&puzzle), (vec![vec![2], vec![1], vec![3]], vec![vec![1, 1], vec![3], vec![1]]));
+    }
+
+    #[test]
+    fn empty_lines_with_and_without_dimensions() {
+        // with the dimensions given, a blank line is a line without runs, just like 0
+        let puzzle = Puzzle::from_non("width 2\nheight 3\nrows\n1\n\n0\ncolumns\n1\n0\n").unwrap();
+        assert_eq!(clues(&puzzle), (vec![vec![1], vec![], vec![]], vec![vec![1], vec![]]));
+
+        // without them, a blank line ends the list
+        let puzzle = Puzzle::from_non("rows\n1 1\n0\n\ncolumns\n1\n0\n1\n").unwrap();
+        assert_eq!(clues(&puzzle), (vec![vec![1, 1], vec![]], vec![vec![1], vec![], vec![1]]));
+    }
+
+    #[test]
+    fn malformed_files_are_errors() {
+        for text in &["width 2\nheight 2\nrows\n1\ncolumns\n1\n1\n",    // only one of two rows
+                      "width 1\nheight 1\nrows\n1\n",
+                      "width 1\nheight 1\nrows\n1a\ncolumns\n1\n",      // not a number
+                      "width x\nrows\n1\ncolumns\n1\n",
+                      "rows\n1\n",                                       // no columns
+                      "width 1\nheight 1\nrows\n2\ncolumns\n1\n"] {     // doesn't fit
+            assert!(matches!(Puzzle::from_non(text), Err(Error::Logic(_))), "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+// vim: set ai et ts=4 sw=4 sts=4:
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use super::grid::SquareStatus;
+use super::puzzle::DEFAULT_MAX_ITERATIONS;
+
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub subdivision: (Option<usize>, Option<usize>), // draw horizontal subdivision lines every Nth row and vertical ones every Nth col, if given
+    pub emit_color: bool,           // whether to output ANSI color escape sequences
+    pub compact: bool,              // one character per square and no subdivision lines, for narrow output
+    pub coordinates: bool,          // label rows and columns with their (0-based) indices instead of their clues
+}
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            subdivision: (Some(5), Some(5)),
+            emit_color: false,
+            compact: false,
+            coordinates: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum QueueOrder {
+    #[default]
+    RowsFirst,   // all rows go ahead of the columns in the initial queue
+    ColsFirst,   // all columns go ahead of the rows in the initial queue
+    Interleaved, // rows and columns take turns in the initial queue
+}
+#[derive(Debug, Clone)]
+pub struct SolveOptions {
+    pub guess_first: SquareStatus,  // status to try first when speculatively changing a square
+    pub max_iterations: usize,      // safety against infinite solver loops, across all levels of speculation
+    pub deadline: Option<Instant>,  // point in time after which solving is aborted, if any
+    pub logic_only: bool,           // stop when logic alone can't make progress, rather than guessing
+    pub max_depth: Option<usize>,   // maximum number of guesses in effect at the same time, if limited
+    pub seed: Option<u64>,          // pick randomly among the best guess candidates with this seed, instead of the first one
+    pub trace_out: Option<Arc<Mutex<fs::File>>>, // file to append a JSON snapshot of the board to after every iteration, if any
+    pub render: RenderOptions,      // how to format intermediate boards in the debug output
+    pub print_every: usize,         // print the intermediate board in the debug output every Nth iteration, or never if 0
+    pub explain: bool,              // annotate each change in the debug output with the deduction that made it
+    pub queue_order: QueueOrder,    // which lines to evaluate first, by direction
+    pub queue_priority: bool,       // re-queue lines of the direction that goes first ahead of the other direction's, rather than at the back
+    pub record_history: bool,       // keep the changes of every iteration around, so that the solve can be exported as a replay
+    pub fill_overlap: bool,         // run the FillOverlap strategy as well (see overlap_strategies); has no effect on wrapped puzzles
+}
+impl Default for SolveOptions {
+    fn default() -> Self {
+        SolveOptions {
+            guess_first: SquareStatus::FilledIn,
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            deadline: None,
+            logic_only: false,
+            max_depth: None,
+            seed: None,
+            trace_out: None,
+            render: RenderOptions::default(),
+            print_every: 1,
+            explain: false,
+            queue_order: QueueOrder::default(),
+            queue_priority: false,
+            record_history: false,
+            fill_overlap: false,
+        }
+    }
+}
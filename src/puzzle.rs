@@ -1,83 +1,534 @@
 // vim: set ai et ts=4 sw=4 sts=4:
 use std::fmt;
-use std::io;
-use std::rc::Rc;
-use std::cell::{Ref, RefMut, RefCell};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard, MappedRwLockReadGuard, MappedRwLockWriteGuard};
 use std::convert::TryFrom;
-use std::collections::{VecDeque, HashSet};
-use std::iter::FromIterator;
-use yaml_rust::Yaml;
+use std::collections::{VecDeque, HashSet, HashMap};
+use std::time::Instant;
+use yaml_rust::{YamlLoader, Yaml};
 use ansi_term::ANSIString;
-use log::{trace, debug, info, log_enabled, Level::Trace};
+use log::debug;
 
-use super::Args;
-use super::grid::{Grid, Square, SquareStatus, Change, Changes, Error, HasGridLocation, CloneGridAware};
-use super::util::{ralign, lalign_colored, ralign_joined_coloreds, Direction, Direction::*, is_a_tty};
-use super::row::{Row, Run};
+use super::options::{RenderOptions, SolveOptions, QueueOrder};
+use super::grid::{Grid, Square, SquareStatus, Change, StatusChange, RunChange, Changes, Error, HasGridLocation, CloneGridAware};
+use super::util::{ralign, lalign_colored, ralign_joined_coloreds, xml_escape, Direction, Direction::*, Rng};
+use super::row::{Row, DirectionalSequence, LineStrategy, default_strategies, wrapped_strategies, solve_line, solve_line_wrapped};
 
 pub struct Solver {
     pub puzzle: Puzzle,
     pub queue: VecDeque<(Direction, usize)>, // queue of rows (vertical or horizontal) to be (re-)evaluated next
+    queue_order: QueueOrder,                 // which direction of lines to put first in the queue, if any
+    queue_priority: bool,                    // whether re-queued lines of that direction go ahead of the other direction's
     pub iterations: usize,                   // total number of rows evaluated for new information to be inferred (whether successfully or not)
-    pub max_iterations: usize,               // safety against infinite solver loops
+    pub max_iterations: usize,               // safety against infinite solver loops; counts iterations across all guesses
+    pub max_depth: Option<usize>,            // maximum number of guesses in effect at once when speculating, if limited
+    pub deadline: Option<Instant>,           // point in time after which solving is aborted, if any
+    progress_callback: Option<Box<dyn FnMut(f64) + Send>>, // invoked with the puzzle's completion ratio after each iteration
+    pub strategies: Vec<Box<dyn LineStrategy>>, // line solving strategies to run on each row, in order
+    pub history: Vec<(Direction, usize, Changes)>, // changes returned by each successful iteration so far, in order (see record_history)
+    pub record_history: bool,                // whether to keep the changes of each iteration in history, e.g. for export_replay
+    pub strict: bool,                        // re-check affected rows right away when changes are applied from outside the solver
+    strategy_stats: HashMap<&'static str, usize>, // number of changes made by each line solving strategy so far, by strategy name
+    speculation: Vec<Speculation>,           // guesses currently in effect, oldest first, each with the solver state from before it
+    known_bad: HashSet<Guess>,               // guesses known to lead to a contradiction under the guesses currently in effect
+    rng: Option<Rng>,                        // if set, guesses are made on one of the most promising squares at random, rather than on the first unknown one
+    reasons: Vec<&'static str>,              // description of the deduction behind each change returned by the last iteration, in the same order
+    stale: HashSet<(Direction, usize)>,      // lines that produced no changes when last evaluated, and whose squares haven't changed since
+    cancelled: Arc<AtomicBool>,              // set from anywhere (e.g. another thread) to abort solving at the next iteration
 }
+
+#[derive(Debug, Clone)]
+pub struct Hint {
+    pub squares: Vec<(usize, usize)>, // (x, y) locations of the squares that can be deduced
+    pub status: SquareStatus,         // the status those squares must have
+    pub reason: String,               // human-readable explanation of the deduction
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Guess {
+    pub x: usize,
+    pub y: usize,
+    pub status: SquareStatus, // the status the square was assumed to have
+}
+impl fmt::Display for Guess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(x={}, y={}) -> {}", self.x, self.y, self.status)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SpeculativeStep {
+    Iteration(Direction, usize, Changes), // a regular solver iteration, on the given row
+    Probed(Changes),                      // logic ran out, but probing single squares turned up these changes
+    Excluded(Vec<Guess>),                 // logic ran out, but these guesses are known to fail; their squares now have the inverse status
+    Guessed(Guess),                       // logic ran out, so a square was given a speculative status
+    Retracted(Guess),                     // the given guess led to a contradiction; its square now has the inverse status
+}
+
+struct Speculation {
+    // a guess currently in effect, along with the solver state from right before it was made
+    guess: Guess,
+    puzzle: Puzzle,
+    queue: VecDeque<(Direction, usize)>,
+    stale: HashSet<(Direction, usize)>,
+    known_bad: HashSet<Guess>,
+}
+
+pub const DEFAULT_MAX_ITERATIONS: usize = 100_000;
+const SVG_CELL_SIZE: usize = 20;           // size of a square in SVG output, in pixels; clues get one cell-sized slot each
+const EXACT_FIT_REASON: &str = "runs exactly filling the line"; // reason given for changes made by try_exact_fit
+const DEADLINE_CHECK_INTERVAL: usize = 64; // number of iterations between deadline checks
 impl Solver {
-    pub fn new(puzzle: Puzzle) -> Self
+    pub fn new(mut puzzle: Puzzle) -> Self
     {
-        Self {
-            queue: VecDeque::from_iter(puzzle.incomplete_rows()),
+        // cross out empty lines right away rather than waiting for the queue to reach them,
+        // and make sure the lines crossing them get (re-)evaluated.
+        let prefilled = puzzle._prefill_empty_lines();
+        let strategies = if puzzle.wrap { wrapped_strategies() } else { default_strategies() };
+        let mut solver = Self {
+            queue: Self::_ordered_queue(puzzle.incomplete_rows(), QueueOrder::default()),
+            queue_order: QueueOrder::default(),
+            queue_priority: false,
             puzzle,
             iterations: 0,
-            max_iterations: 100_000,
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            max_depth: None,
+            deadline: None,
+            progress_callback: None,
+            strategies,
+            history: Vec::new(),
+            record_history: false,
+            strict: false,
+            strategy_stats: HashMap::new(),
+            speculation: Vec::new(),
+            known_bad: HashSet::new(),
+            rng: None,
+            reasons: Vec::new(),
+            stale: HashSet::new(),
+            cancelled: Arc::new(AtomicBool::new(false)),
+        };
+        for (_, _, changes) in &prefilled {
+            for change in changes {
+                solver._refeed_change(change);
+            }
+        }
+        solver.history = prefilled;
+        solver
+    }
+    pub fn on_progress<F>(&mut self, callback: F)
+        where F: FnMut(f64) + Send + 'static
+    {
+        self.progress_callback = Some(Box::new(callback));
+    }
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.deadline = Some(deadline);
+    }
+    pub fn set_seed(&mut self, seed: u64) {
+        // makes guesses on one of the most promising squares at random (see Puzzle::guess_candidates), in a
+        // sequence that's reproducible for the same seed
+        self.rng = Some(Rng::new(seed));
+    }
+    pub fn set_queue_order(&mut self, queue_order: QueueOrder) {
+        // changes which lines are evaluated first from here on, and rearranges the lines currently queued to match
+        self.queue_order = queue_order;
+        self.queue = Self::_ordered_queue(self.queue.drain(..).collect(), queue_order);
+    }
+    pub fn set_queue_priority(&mut self, queue_priority: bool) {
+        // when set, lines that get re-queued go ahead of the queued lines in the other direction if their direction
+        // is the one to be evaluated first (see set_queue_order), instead of at the back of the queue
+        self.queue_priority = queue_priority;
+    }
+    fn _ordered_queue(lines: Vec<(Direction, usize)>, queue_order: QueueOrder) -> VecDeque<(Direction, usize)> {
+        // arranges the given lines into a queue according to the given order, keeping the order within each direction
+        let (rows, cols): (Vec<_>, Vec<_>) = lines.into_iter().partition(|&(d, _)| d == Horizontal);
+        match queue_order {
+            QueueOrder::RowsFirst   => rows.into_iter().chain(cols).collect(),
+            QueueOrder::ColsFirst   => cols.into_iter().chain(rows).collect(),
+            QueueOrder::Interleaved => {
+                let mut queue = VecDeque::new();
+                let (mut rows, mut cols) = (rows.into_iter(), cols.into_iter());
+                loop {
+                    match (rows.next(), cols.next()) {
+                        (None, None) => break,
+                        (row, col)   => queue.extend(row.into_iter().chain(col)),
+                    }
+                }
+                queue
+            },
+        }
+    }
+    pub fn queue_len(&self) -> usize {
+        self.queue.len()
+    }
+    pub fn prioritize(&mut self, direction: Direction, index: usize) -> Result<(), Error> {
+        // moves the given line to the front of the queue (adding it if it wasn't queued), so that it's evaluated next.
+        // the line is evaluated even if it had nothing new to work with last time.
+        let num_lines = match direction {
+            Horizontal => self.puzzle.height(),
+            Vertical   => self.puzzle.width(),
+        };
+        if index >= num_lines {
+            return Err(Error::Logic(format!("Cannot prioritize {} row {}: puzzle only has {} of them", direction, index, num_lines)));
+        }
+        self.queue.retain(|&line| line != (direction, index));
+        self.queue.push_front((direction, index));
+        self.stale.remove(&(direction, index));
+        Ok(())
+    }
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        // returns a handle to this solver's cancellation flag; once it's set, the next iteration returns
+        // Error::Cancelled instead of doing any more work.
+        Arc::clone(&self.cancelled)
+    }
+    pub fn strategy_stats(&self) -> &HashMap<&'static str, usize> {
+        // number of changes contributed by each line solving strategy so far, keyed by strategy name.
+        // changes made by exact fits are counted as "try_exact_fit".
+        &self.strategy_stats
+    }
+    pub fn last_reasons(&self) -> &[&'static str] {
+        // describes why each of the changes returned by the last iteration was made, in the same order as the changes
+        &self.reasons
+    }
+    pub fn apply_ascii_state(&mut self, art: &str) -> Result<Changes, Error> {
+        // applies the given ASCII art grid state to the puzzle, and feeds the affected rows back into the queue
+        let changes = self.puzzle.apply_ascii_state(art)?;
+        for change in &changes {
+            self._refeed_change(change);
+            self._check_external_change(change)?;
+        }
+        Ok(changes)
+    }
+    pub fn set_square_status(&mut self, x: usize, y: usize, status: SquareStatus) -> Result<Option<Change>, Error> {
+        // sets the status of a single square from outside of the solver (e.g. a manual edit),
+        // and feeds the affected rows back into the queue
+        let change = self.puzzle.get_square_mut(x, y).set_status(status)?.map(Change::from);
+        if let Some(change) = &change {
+            self._refeed_change(change);
+            self._check_external_change(change)?;
+        }
+        Ok(change)
+    }
+    fn _check_external_change(&mut self, change: &Change) -> Result<(), Error> {
+        // in strict mode, recalculates the possible run placements of the row and column affected by a change
+        // that was made from outside of the solver, so that a bad edit is reported right away (and blamed on
+        // that edit) instead of surfacing as an inconsistency somewhere down the line. the change is not undone.
+        if !self.strict {
+            return Ok(());
         }
+        for (d, i) in [(Horizontal, change.get_row()), (Vertical, change.get_col())] {
+            let row = self.puzzle.get_row_mut(d, i);
+            if row.is_completed() { continue; }
+            if let Err(e) = row.update_possible_run_placements() {
+                return Err(Error::Logic(format!("{} conflicts with the clues of {} row {}: {}", change, d, i, e)));
+            }
+        }
+        Ok(())
+    }
+    pub fn hint(&self) -> Option<Hint> {
+        // finds the next square(s) whose status can be deduced with line logic, without changing the solver's state.
+        // runs the strategies one by one on a copy of the puzzle, going over the lines in the queue first and the
+        // remaining incomplete lines after that, and describes the first one that changes the status of a square.
+        // returns None if no such deduction could be found (or the puzzle is in an inconsistent state).
+        let mut trial = self.puzzle.clone();
+        let mut lines = self.queue.iter().cloned().collect::<Vec<_>>();
+        lines.extend(trial.incomplete_rows().into_iter().filter(|line| !self.queue.contains(line)));
+
+        for (d, i) in lines {
+            let wrap = trial.wrap;
+            let row = trial.get_row_mut(d, i);
+            let changes = if wrap { vec![] } else { row.try_exact_fit().ok()? }; // exact fits don't pin down wrapped runs
+            if let Some(hint) = Self::_hint_from_changes(&changes, EXACT_FIT_REASON, d, i) {
+                return Some(hint);
+            }
+            for strategy in &self.strategies {
+                let changes = strategy.apply(row).ok()?;
+                if let Some(hint) = Self::_hint_from_changes(&changes, strategy.describe(), d, i) {
+                    return Some(hint);
+                }
+            }
+        }
+        None
+    }
+    fn _hint_from_changes(changes: &[Change], description: &str, direction: Direction, index: usize) -> Option<Hint> {
+        // builds a hint out of the first status change and the others like it, i.e. with the same new status
+        // and (if known) assigned to the same run. changes that only assign runs aren't visible to the player,
+        // so they don't make for a hint by themselves.
+        let run_of = |x: usize, y: usize| changes.iter().find_map(|change| match change {
+            Change::Run(c) if c.col == x && c.row == y => Some(c.new),
+            _                                          => None,
+        });
+        let (status, run) = changes.iter().find_map(|change| match change {
+            Change::Status(c) => Some((c.new, run_of(c.col, c.row))),
+            _                 => None,
+        })?;
+        let squares = changes.iter().filter_map(|change| match change {
+            Change::Status(c) if c.new == status && run_of(c.col, c.row) == run => Some((c.col, c.row)),
+            _                                                                   => None,
+        }).collect::<Vec<_>>();
+        let reason = match run {
+            Some(run) => format!("{} in {} row {} (run #{})", description, direction, index, run),
+            None      => format!("{} in {} row {}", description, direction, index),
+        };
+        Some(Hint { squares, status, reason })
     }
-    pub fn apply_and_feed_change(&mut self, change: &Change) {
-        self.puzzle.apply_change((*change).clone()).expect("");
+    pub fn export_replay(&self) -> String {
+        // serializes the changes made by each iteration so far in a compact text format, one line per
+        // iteration: the line that was evaluated, followed by the changes that were made to it, e.g.
+        //
+        //     H:3 s:3:0:.:# r:3:0:H:-:0 s:3:1:.:x
+        //
+        // status changes are "s:<row>:<col>:<old>:<new>", run changes are "r:<row>:<col>:<direction>:<old>:<new>".
+        // can be re-applied with Puzzle::apply_replay. only the empty lines crossed out up front are kept track of
+        // unless record_history is set, so set it before solving to get the whole replay.
+        let mut result = String::new();
+        for (d, i, changes) in &self.history {
+            result.push_str(&format!("{}:{}", replay_direction_char(*d), i));
+            for change in changes {
+                result.push(' ');
+                result.push_str(&match change {
+                    Change::Status(c) => format!("s:{}:{}:{}:{}", c.row, c.col,
+                                                 replay_status_char(c.old), replay_status_char(c.new)),
+                    Change::Run(c)    => format!("r:{}:{}:{}:{}:{}", c.row, c.col, replay_direction_char(c.direction),
+                                                 c.old.map_or("-".to_string(), |x| x.to_string()), c.new),
+                });
+            }
+            result.push('\n');
+        }
+        result
+    }
+    pub fn apply_and_feed_change(&mut self, change: &Change) -> Result<(), Error> {
+        self.puzzle.apply_change((*change).clone())?;
         self._refeed_change(change);
+        Ok(())
+    }
+    pub fn probe_cells(&mut self) -> Result<Changes, Error> {
+        // one-ply probing: for each unknown square, tentatively set it to filled in on a copy of its row and
+        // column, and run the line solver on each of them. if that produces an error, the square must be
+        // crossed out instead (and vice versa). returns the changes that were inferred and applied this way.
+        let mut changes = Vec::<Change>::new();
+        for (x, y) in self.puzzle.unknown_squares() {
+            if self.puzzle.get_square(x, y).get_status() != SquareStatus::Unknown {
+                continue; // might have been inferred by an earlier probe
+            }
+            let filled_ok  = self._probe_cell(x, y, SquareStatus::FilledIn);
+            let crossed_ok = self._probe_cell(x, y, SquareStatus::CrossedOut);
+            let inferred_status = match (filled_ok, crossed_ok) {
+                (true,  true)  => continue, // nothing learned
+                (true,  false) => SquareStatus::FilledIn,
+                (false, true)  => SquareStatus::CrossedOut,
+                (false, false) => return Err(Error::Logic(format!(
+                    "Inconsistency: square (col={}, row={}) can be neither filled in nor crossed out", x, y))),
+            };
+            debug!("probing: square (x={}, y={}) must be {}", x, y, inferred_status);
+            let change = Change::from(StatusChange::new(y, x, SquareStatus::Unknown, inferred_status));
+            self.apply_and_feed_change(&change)?;
+            changes.push(change);
+        }
+        Ok(changes)
+    }
+    pub fn guesses(&self) -> impl Iterator<Item = &Guess> {
+        // the speculative changes that the current puzzle state depends on, oldest first
+        self.speculation.iter().map(|speculation| &speculation.guess)
+    }
+    pub fn guess(&mut self, status: SquareStatus) -> Result<Option<Guess>, Error> {
+        // speculatively gives a square the given status, remembering the current state so that the guess can be
+        // retracted later on. the square is the first unknown one, or a random pick among the most promising ones
+        // if a seed was set. every line gets evaluated again afterwards. returns None if there are no unknown
+        // squares left, or Error::DepthLimit if the maximum number of guesses is already in effect.
+        let (x, y) = match (&mut self.rng, self.puzzle.unknown_squares().first()) {
+            (_, None)            => return Ok(None),
+            (Some(rng), Some(_)) => {
+                let candidates = self.puzzle.guess_candidates();
+                candidates[rng.gen_below(candidates.len())]
+            },
+            (None, Some(&pos))   => pos,
+        };
+        let guess = Guess { x, y, status };
+        if let Some(max_depth) = self.max_depth {
+            if self.speculation.len() >= max_depth {
+                debug!("guessing {} would exceed the maximum depth of {}", guess, max_depth);
+                return Err(Error::DepthLimit);
+            }
+        }
+        self.speculation.push(Speculation {
+            guess,
+            puzzle: self.puzzle.clone(),
+            queue: self.queue.clone(),
+            stale: self.stale.clone(),
+            known_bad: self.known_bad.clone(),
+        });
+        self.puzzle.get_square_mut(x, y).set_status(status)?;
+        self.queue = Self::_ordered_queue(self.puzzle.incomplete_rows(), self.queue_order);
+        self.stale.clear();
+        Ok(Some(guess))
+    }
+    pub fn retract_guess(&mut self) -> Option<Guess> {
+        // undoes the most recent guess, and gives its square the inverse status instead. if that conflicts with
+        // the guesses made before it as well, those are retracted in turn. returns the guess whose inverse was
+        // applied, or None if the guesses ran out (i.e. the puzzle has no solution).
+        while let Some(speculation) = self.speculation.pop() {
+            let guess = speculation.guess;
+            self._restore(speculation);
+            // the guess fails for as long as the guesses before it are in effect, so it needn't be tried again
+            self.known_bad.insert(guess);
+            if self.set_square_status(guess.x, guess.y, Self::_inverse(guess.status)).is_ok() {
+                return Some(guess);
+            }
+        }
+        None
+    }
+    pub fn abandon_guesses(&mut self) {
+        // drops all guesses in effect, and goes back to the state from right before the first one was made;
+        // e.g. to report how far logic got after speculation was cut short
+        let first = self.speculation.drain(..).next();
+        if let Some(first) = first {
+            self._restore(first);
+        }
+    }
+    pub fn next_speculative(&mut self, guess_first: SquareStatus) -> Option<Result<SpeculativeStep, Error>> {
+        // like next(), but instead of stopping when logic runs out, carries on. squares are probed first (see
+        // probe_cells); if that doesn't turn up anything, any square for which a guess of guess_first is known
+        // to fail gets the inverse status, and failing that, a new guess is made. contradictions retract the most
+        // recent guess. returns None once the puzzle is completed; errors are only returned if they can't be
+        // blamed on a guess, or if solving was cut short.
+        let error = match self.next() {
+            Some(Ok((d, i, changes))) => return Some(Ok(SpeculativeStep::Iteration(d, i, changes))),
+            Some(Err(e @ Error::Timeout)) | Some(Err(e @ Error::Cancelled)) | Some(Err(e @ Error::IterationLimit)) => return Some(Err(e)),
+            Some(Err(e)) => e,
+            None if self.puzzle.is_completed() => return None,
+            None => match self._speculate(guess_first) {
+                Ok(Some(step))          => return Some(Ok(step)),
+                Ok(None)                => return None,
+                Err(Error::DepthLimit)  => return Some(Err(Error::DepthLimit)),
+                Err(e)                  => e,
+            },
+        };
+        match self.retract_guess() {
+            Some(guess) => Some(Ok(SpeculativeStep::Retracted(guess))),
+            None        => Some(Err(error)),
+        }
+    }
+    fn _speculate(&mut self, guess_first: SquareStatus) -> Result<Option<SpeculativeStep>, Error> {
+        // the next thing to try once logic has run out; see next_speculative
+        let changes = self.probe_cells()?;
+        if !changes.is_empty() {
+            return Ok(Some(SpeculativeStep::Probed(changes)));
+        }
+        let excluded = self.puzzle.unknown_squares().into_iter()
+                                                    .map(|(x, y)| Guess { x, y, status: guess_first })
+                                                    .filter(|guess| self.known_bad.contains(guess))
+                                                    .collect::<Vec<_>>();
+        if !excluded.is_empty() {
+            for guess in &excluded {
+                self.set_square_status(guess.x, guess.y, Self::_inverse(guess.status))?;
+            }
+            return Ok(Some(SpeculativeStep::Excluded(excluded)));
+        }
+        Ok(self.guess(guess_first)?.map(SpeculativeStep::Guessed))
+    }
+    fn _restore(&mut self, speculation: Speculation) {
+        self.puzzle = speculation.puzzle;
+        self.queue = speculation.queue;
+        self.stale = speculation.stale;
+        self.known_bad = speculation.known_bad;
+    }
+    fn _inverse(status: SquareStatus) -> SquareStatus {
+        match status {
+            SquareStatus::FilledIn => SquareStatus::CrossedOut,
+            _                      => SquareStatus::FilledIn,
+        }
+    }
+    fn _probe_cell(&self, x: usize, y: usize, status: SquareStatus) -> bool {
+        // gives the given square the given status in copies of the state of its row and its column, and solves
+        // each of those lines in isolation. returns false if that resulted in a conflict, true otherwise.
+        let line_solver = if self.puzzle.wrap { solve_line_wrapped } else { solve_line };
+        [(Horizontal, y, x), (Vertical, x, y)].iter().all(|&(direction, index, at)| {
+            let row = self.puzzle.get_row(direction, index);
+            let clues = row.runs().iter().map(|run| run.length).collect::<Vec<_>>();
+            let mut cells = (0..row.length).map(|pos| row.get_square(pos).get_status()).collect::<Vec<_>>();
+            cells[at] = status;
+            line_solver(row.length, &clues, &cells).is_ok()
+        })
     }
     fn _refeed_change(&mut self, change: &Change) {
         // takes a change and feeds the row and column that it affected back into the queue.
         let (row, col) = (change.get_row(), change.get_col());
         let h_value = (self.puzzle.rows[row].direction, self.puzzle.rows[row].index);
         let v_value = (self.puzzle.cols[col].direction, self.puzzle.cols[col].index);
-        if !self.queue.contains(&v_value) { self.queue.push_back(v_value); }
-        if !self.queue.contains(&h_value) { self.queue.push_back(h_value); }
+        // lines only look at the run assignments in their own direction, so a run change doesn't give
+        // the line crossing it anything new to work with; it's still queued, but skipped if it was stale.
+        match change {
+            Change::Status(_) => { self.stale.remove(&h_value); self.stale.remove(&v_value); },
+            Change::Run(x)    => { self.stale.remove(if x.direction == Horizontal { &h_value } else { &v_value }); },
+        }
+        self._enqueue(v_value);
+        self._enqueue(h_value);
+    }
+    fn _enqueue(&mut self, line: (Direction, usize)) {
+        // adds a line to the queue (unless it's already in there); at the back, unless queue priority is on and its
+        // direction is to be evaluated first, in which case it goes ahead of the first queued line in the other direction.
+        if self.queue.contains(&line) {
+            return;
+        }
+        let preferred = match self.queue_order {
+            _ if !self.queue_priority => None,
+            QueueOrder::Interleaved   => None,
+            QueueOrder::RowsFirst     => Some(Horizontal),
+            QueueOrder::ColsFirst     => Some(Vertical),
+        };
+        match preferred {
+            Some(direction) if line.0 == direction => {
+                let at = self.queue.iter().position(|&(d, _)| d != direction).unwrap_or(self.queue.len());
+                self.queue.insert(at, line);
+            },
+            _ => self.queue.push_back(line),
+        }
     }
     fn _iter_next(&mut self) -> Option<<Solver as Iterator>::Item>
     {
-        macro_rules! changes_or_return {
-            ($exp:expr) => {{
-                match $exp {
-                    Ok(changes) => changes,
-                    Err(e)      => return Some(Err(e)),
-                }
-            }}
-        };
         // iterate over the queue and run solver logic on them until some changes are found, and return them;
         // if we're out of rows to investigate, return None.
         while let Some((d,i)) = self.queue.pop_front()
         {
+            if self.stale.contains(&(d,i)) {
+                continue; // evaluated before without result, and nothing changed since
+            }
+            if self.cancelled.load(Ordering::Relaxed) {
+                self.queue.push_front((d,i)); // not evaluated yet, keep it for when solving resumes
+                return Some(Err(Error::Cancelled));
+            }
             self.iterations += 1;
             if self.iterations >= self.max_iterations {
-                panic!("max iterations exceeded, aborting");
+                return Some(Err(Error::IterationLimit));
             }
-
-            let row: &mut Row = self.puzzle.get_row_mut(d,i);
-
-            // before doing any further work, check whether this row is already_completed
-            // (includes handling of trivial cases like empty rows etc)
-            let mut changes = Vec::<Change>::new();
-            changes.extend(changes_or_return!(row.check_completed_runs()));
-            changes.extend(changes_or_return!(row.check_completed()));
-
-            if !row.is_completed() {
-                if let Err(e) = row.update_possible_run_placements() {
-                    return Some(Err(e));
+            // only consult the clock every so often (and on the first iteration), it's not free
+            if let Some(deadline) = self.deadline {
+                if self.iterations % DEADLINE_CHECK_INTERVAL == 1 && Instant::now() >= deadline {
+                    return Some(Err(Error::Timeout));
                 }
-                changes.extend(changes_or_return!(row.infer_run_assignments()));
-                changes.extend(changes_or_return!(row.infer_status_assignments()));
             }
 
+            let wrap = self.puzzle.wrap;
+            let row: &mut Row = self.puzzle.get_row_mut(d,i);
+            // saturated rows are determined outright, no need to go through the strategies for those
+            // (unless they wrap around, in which case the runs could still be rotated)
+            let exact_fit = if wrap { Ok(vec![]) } else { row.try_exact_fit() };
+            self.reasons.clear();
+            let changes = match exact_fit {
+                Ok(changes) if !changes.is_empty() => {
+                    *self.strategy_stats.entry("try_exact_fit").or_insert(0) += changes.len();
+                    self.reasons.resize(changes.len(), EXACT_FIT_REASON);
+                    Ok(changes)
+                },
+                Ok(_)  => row.apply_strategies_explained(&self.strategies, &mut self.strategy_stats, &mut self.reasons),
+                Err(e) => Err(e),
+            };
+            let changes = match changes {
+                Ok(changes) => changes,
+                Err(e)      => return Some(Err(e)),
+            };
+
             if changes.len() > 0 {
                 // found some changes in this row; feed the affected rows and columns
                 // back into the queue, and return the changes made.
@@ -87,6 +538,7 @@ impl Solver {
                 return Some(Ok((d, i, changes)));
             } else {
                 // no changes made, try next row in the queue.
+                self.stale.insert((d,i));
             }
         }
         None // out of actions
@@ -96,34 +548,122 @@ impl Iterator for Solver {
     type Item = Result<(Direction, usize, Changes), Error>; // row direction, index and list of changes applied in this iteration, or an error indicating a problem
 
     fn next(&mut self) -> Option<Self::Item> {
-        self._iter_next()
+        let result = self._iter_next();
+        if let Some(Ok(item)) = &result {
+            if self.record_history {
+                self.history.push(item.clone());
+            }
+        }
+        if result.is_some() {
+            if let Some(callback) = &mut self.progress_callback {
+                callback(self.puzzle.completion_ratio());
+            }
+        }
+        result
+    }
+}
+
+fn replay_status_char(status: SquareStatus) -> char {
+    match status {
+        SquareStatus::FilledIn   => '#',
+        SquareStatus::CrossedOut => 'x',
+        SquareStatus::Unknown    => '.',
+    }
+}
+fn replay_direction_char(direction: Direction) -> char {
+    match direction {
+        Horizontal => 'H',
+        Vertical   => 'V',
+    }
+}
+fn parse_replay_status(field: &str) -> Result<SquareStatus, Error> {
+    match field {
+        "#" => Ok(SquareStatus::FilledIn),
+        "x" => Ok(SquareStatus::CrossedOut),
+        "." => Ok(SquareStatus::Unknown),
+        _   => Err(Error::Logic(format!("Invalid square status '{}' in replay", field))),
+    }
+}
+fn parse_replay_direction(field: &str) -> Result<Direction, Error> {
+    match field {
+        "H" => Ok(Horizontal),
+        "V" => Ok(Vertical),
+        _   => Err(Error::Logic(format!("Invalid direction '{}' in replay", field))),
     }
 }
+fn parse_replay_number(field: &str) -> Result<usize, Error> {
+    field.parse::<usize>()
+         .map_err(|_| Error::Logic(format!("Invalid number '{}' in replay", field)))
+}
 
 #[derive(Debug)]
 pub struct Puzzle {
     pub rows: Vec<Row>,
     pub cols: Vec<Row>,
-    pub grid: Rc<RefCell<Grid>>,
+    pub grid: Arc<RwLock<Grid>>,
+    pub name: Option<String>,
+    pub wrap: bool, // whether runs can wrap around from the end of a row or column onto its start (torus variant)
 }
 
 impl Puzzle {
-    pub fn new(grid: &Rc<RefCell<Grid>>,
+    pub fn new(grid: &Arc<RwLock<Grid>>,
                row_run_lengths: &Vec<Vec<usize>>,
                col_run_lengths: &Vec<Vec<usize>>) -> Self
     {
-        let rows = (0..grid.borrow().height()).map(|y| Row::new(grid, Horizontal, y, &row_run_lengths[y]))
+        let rows = (0..grid.read().height()).map(|y| Row::new(grid, Horizontal, y, &row_run_lengths[y]))
                                               .collect::<Vec<_>>();
-        let cols = (0..grid.borrow().width()).map(|x| Row::new(grid, Vertical, x, &col_run_lengths[x]))
+        let cols = (0..grid.read().width()).map(|x| Row::new(grid, Vertical, x, &col_run_lengths[x]))
                                              .collect::<Vec<_>>();
         Puzzle {
             rows: rows,
             cols: cols,
-            grid: Rc::clone(grid),
+            grid: Arc::clone(grid),
+            name: None,
+            wrap: false,
         }
     }
-    pub fn width(&self) -> usize { self.grid.borrow().width() }
-    pub fn height(&self) -> usize { self.grid.borrow().height() }
+    pub fn prefill_empty_lines(&mut self) -> Changes {
+        // crosses out every square in rows and columns whose clue is empty (or only 0-length runs),
+        // and marks them completed. lines that already contain a filled in square are left alone
+        // so that the conflict gets reported by the solver when it evaluates them.
+        self._prefill_empty_lines()
+            .into_iter()
+            .flat_map(|(_, _, changes)| changes)
+            .collect()
+    }
+    fn _prefill_empty_lines(&mut self) -> Vec<(Direction, usize, Changes)> {
+        // see prefill_empty_lines; returns the changes made to each line separately
+        let mut result = Vec::<(Direction, usize, Changes)>::new();
+        for row in self.rows.iter_mut().chain(self.cols.iter_mut()) {
+            if row.is_completed() || !row.is_trivially_empty() {
+                continue;
+            }
+            if (0..row.length).any(|at| row.get_square(at).get_status() == SquareStatus::FilledIn) {
+                continue;
+            }
+            let changes = row.check_completed().expect("crossing out an empty line cannot conflict");
+            if !changes.is_empty() {
+                result.push((row.direction, row.index, changes));
+            }
+        }
+        result
+    }
+    pub fn initial_deductions(&mut self) -> Result<Changes, Error> {
+        // runs overlap filling on every row and column once, and returns all changes made. these are the
+        // squares that can be deduced from the clues alone (e.g. the middle of long runs), without needing
+        // to go through the solver.
+        let mut changes = Vec::<Change>::new();
+        for row in self.rows.iter_mut().chain(self.cols.iter_mut()) {
+            if row.is_completed() {
+                continue;
+            }
+            row.update_possible_run_placements()?;
+            changes.extend(row.fill_overlap()?);
+        }
+        Ok(changes)
+    }
+    pub fn width(&self) -> usize { self.grid.read().width() }
+    pub fn height(&self) -> usize { self.grid.read().height() }
 
     pub fn incomplete_rows(&self) -> Vec<(Direction, usize)> {
         // returns a vector of (direction, index) pairs of rows (either horizontal or vertical)
@@ -134,43 +674,221 @@ impl Puzzle {
         res
     }
 
-    pub fn from_yaml(doc: &Yaml) -> Puzzle
+    pub fn from_clues(row_clues: Vec<Vec<usize>>, col_clues: Vec<Vec<usize>>) -> Result<Puzzle, Error>
     {
-        let row_run_lengths = Self::_parse_row(&doc["rows"]);
-        let col_run_lengths = Self::_parse_row(&doc["cols"]);
-        let grid = Rc::new(RefCell::new(
-            Grid::new(col_run_lengths.len(), row_run_lengths.len())
+        // builds a puzzle from plain lists of run lengths, sizing the grid from the number of clues
+        // in each direction. fails if the clues can't possibly describe the same grid.
+        if row_clues.is_empty() || col_clues.is_empty() {
+            return Err(Error::Logic(format!(
+                "Puzzle must have at least one row and one column (got {} rows, {} cols)", row_clues.len(), col_clues.len())));
+        }
+        let grid = Arc::new(RwLock::new(
+            Grid::new(col_clues.len(), row_clues.len())?
         ));
-        Puzzle::new(&grid, &row_run_lengths, &col_run_lengths)
+        let puzzle = Puzzle::new(&grid, &row_clues, &col_clues);
+        puzzle.is_feasible()?;
+        Ok(puzzle)
+    }
+
+    pub fn from_pixels(pixels: &[Vec<bool>]) -> Result<Puzzle, Error>
+    {
+        // builds a puzzle whose solution is the given picture (one bool per square, true meaning filled in),
+        // by reading the run lengths off of each row and column.
+        let height = pixels.len();
+        let width = pixels.first().map_or(0, |row| row.len());
+        if let Some(y) = pixels.iter().position(|row| row.len() != width) {
+            return Err(Error::Logic(format!(
+                "Picture row {} has {} squares, but the first row has {}", y, pixels[y].len(), width)));
+        }
+        let runs_of = |line: &mut dyn Iterator<Item=bool>| {
+            let mut runs = Vec::<usize>::new();
+            let mut current = 0;
+            for filled in line.chain(std::iter::once(false)) {
+                if filled {
+                    current += 1;
+                } else if current > 0 {
+                    runs.push(current);
+                    current = 0;
+                }
+            }
+            runs
+        };
+        let row_clues = (0..height).map(|y| runs_of(&mut (0..width).map(|x| pixels[y][x])))
+                                   .collect::<Vec<_>>();
+        let col_clues = (0..width).map(|x| runs_of(&mut (0..height).map(|y| pixels[y][x])))
+                                  .collect::<Vec<_>>();
+        Self::from_clues(row_clues, col_clues)
     }
 
-    fn _parse_row(input: &Yaml) -> Vec<Vec<usize>> {
-		let list: &Vec<Yaml> = input.as_vec().unwrap();
+    pub fn is_feasible(&self) -> Result<(), Error>
+    {
+        // checks whether the clues could describe a solvable puzzle at all, judging from their structure
+        // alone: every row must have room for its runs, and the rows and columns must agree on the
+        // total number of filled in squares. catches obviously broken input before any solving is done.
+        if let Some(row) = self.rows.iter().chain(self.cols.iter()).find(|row| !row.is_feasible()) {
+            return Err(Error::Logic(format!(
+                "Runs [{}] of {} row {} need at least {} squares, but the row is only {} long",
+                row.runs().iter().map(|run| run.length.to_string()).collect::<Vec<_>>().join(", "),
+                row.direction, row.index, row.min_length(), row.length)));
+        }
+        let total_length = |rows: &Vec<Row>| rows.iter().map(|row| row.clue_total()).sum::<usize>();
+        let (row_total, col_total) = (total_length(&self.rows), total_length(&self.cols));
+        if row_total != col_total {
+            return Err(Error::Logic(format!(
+                "Row clues fill in {} squares in total, but column clues fill in {}", row_total, col_total)));
+        }
+        Ok(())
+    }
+
+    pub fn from_yaml(doc: &Yaml) -> Result<Puzzle, Error>
+    {
+        // rows are as long as there are columns and vice versa; if either isn't a list, parsing it reports that
+        let num_entries = |key: &str| doc[key].as_vec().map_or(usize::MAX, Vec::len);
+        let row_run_lengths = Self::_parse_row(&doc["rows"], "rows", num_entries("cols"))?;
+        let col_run_lengths = Self::_parse_row(&doc["cols"], "cols", num_entries("rows"))?;
+        let mut puzzle = Puzzle::from_clues(row_run_lengths, col_run_lengths)?;
+        puzzle.name = doc["name"].as_str()
+                                 .or_else(|| doc["title"].as_str())
+                                 .map(String::from);
+        puzzle.wrap = doc["wrap"].as_bool().unwrap_or(false);
+        if !doc["prefill"].is_badvalue() {
+            let prefill = Self::_parse_prefill(&doc["prefill"])?;
+            puzzle.apply_prefill(&prefill)?;
+        }
+        Ok(puzzle)
+    }
+
+    pub fn all_from_yaml(doc_str: &str) -> Result<Vec<Puzzle>, Error>
+    {
+        // parses every (---separated) document in the given YAML string as a puzzle definition
+        let docs: Vec<Yaml> = YamlLoader::load_from_str(doc_str)
+                                         .map_err(|e| Error::Logic(format!("Failed to parse puzzle YAML: {}", e)))?;
+        docs.iter()
+            .filter(|doc| !doc.is_null())
+            .map(Puzzle::from_yaml)
+            .collect()
+    }
+
+    pub fn apply_prefill(&mut self, prefill: &[(usize, usize, SquareStatus)]) -> Result<Changes, Error> {
+        // sets the given (row, col, status) squares as known before solving starts, and checks that
+        // every line that was touched can still be solved with respect to its clues.
+        let mut changes = Vec::<Change>::new();
+        for &(y, x, status) in prefill {
+            if y >= self.height() || x >= self.width() {
+                return Err(Error::Logic(format!(
+                    "Prefilled square (row {}, col {}) is outside of the {}x{} puzzle", y, x, self.width(), self.height())));
+            }
+            if let Some(change) = self.get_square_mut(x, y).set_status(status)? {
+                changes.push(Change::from(change));
+            }
+        }
+
+        let touched_lines = changes.iter()
+                                   .flat_map(|c| vec![(Horizontal, c.get_row()), (Vertical, c.get_col())])
+                                   .collect::<HashSet<_>>();
+        for (direction, index) in touched_lines {
+            let row = self.get_row(direction, index);
+            let clues = row.runs().iter().map(|run| run.length).collect::<Vec<_>>();
+            let cells = (0..row.length).map(|at| row.get_square(at).get_status()).collect::<Vec<_>>();
+            let line_solver = if self.wrap { solve_line_wrapped } else { solve_line };
+            line_solver(row.length, &clues, &cells).map_err(|e| match e {
+                // solve_line works on a standalone copy of the line, so translate back to puzzle coordinates
+                Error::Contradiction { reason, conflicting_squares, .. } => Error::Contradiction {
+                    direction,
+                    index,
+                    reason: format!("prefilled squares conflict with its clues; {}", reason),
+                    conflicting_squares: conflicting_squares.iter().map(|&(at, _)| row.square_index(at)).collect(),
+                },
+                e => Error::Logic(format!(
+                    "Prefilled squares in {} row {} conflict with its clues: {}", direction, index, e)),
+            })?;
+        }
+        Ok(changes)
+    }
+
+    fn _parse_row(input: &Yaml, key: &str, line_length: usize) -> Result<Vec<Vec<usize>>, Error> {
+        // parses the clues of all rows or columns; key is the name of the YAML entry they came from
+        let list: &Vec<Yaml> = input.as_vec().ok_or_else(|| Error::Logic(format!(
+            "Expected {} to be a list of clues, got {:?}", key, input)))?;
+        if list.is_empty() {
+            return Err(Error::Logic(format!("Puzzle must have at least one entry in {}", key)));
+        }
         list.iter()
-		    .map(|yaml_val| Self::_parse_row_runs(yaml_val))
-			.collect()
+            .map(|runs| Self::_parse_row_runs(runs, line_length))
+            .collect()
     }
 
-    fn _parse_row_runs(input: &Yaml) -> Vec<usize> {
+    fn _parse_prefill(input: &Yaml) -> Result<Vec<(usize, usize, SquareStatus)>, Error> {
+        // parses a list of [row, col, status] triples, where status is one of the SquareStatus names
+        let invalid = |entry: &Yaml| Error::Logic(format!(
+            "Invalid prefill entry {:?}; expected [row, col, FilledIn|CrossedOut]", entry));
+        let list = input.as_vec().ok_or_else(|| Error::Logic("Expected prefill to be a list".to_string()))?;
+        list.iter().map(|entry| {
+            match entry.as_vec().map(|v| v.as_slice()) {
+                Some([row, col, status]) => {
+                    let row = row.as_i64().and_then(|v| usize::try_from(v).ok()).ok_or_else(|| invalid(entry))?;
+                    let col = col.as_i64().and_then(|v| usize::try_from(v).ok()).ok_or_else(|| invalid(entry))?;
+                    let status = status.as_str().and_then(|s| SquareStatus::try_from(s).ok()).ok_or_else(|| invalid(entry))?;
+                    Ok((row, col, status))
+                },
+                _ => Err(invalid(entry)),
+            }
+        }).collect()
+    }
+
+    fn _parse_row_runs(input: &Yaml, line_length: usize) -> Result<Vec<usize>, Error> {
+        let invalid_length = |value: &dyn fmt::Debug| Error::Logic(format!("Unexpected run length: {:?}", value));
         match input {
-            Yaml::String(_)  => { input.as_str().unwrap()
-                                       .split_whitespace()
-                                       .map(|int| int.trim().parse().unwrap())
-                                       .collect()
+            Yaml::String(s)  => { let runs = s.split_whitespace()
+                                              .map(|token| Self::_parse_run_token(token, line_length))
+                                              .collect::<Result<Vec<_>, _>>()?;
+                                  Ok(runs.concat())
                                 },
-            Yaml::Integer(_) => { vec![ usize::try_from(input.as_i64().unwrap()).unwrap() ] }
-            Yaml::Null       => { vec![] }
-            _ => panic!("Unexpected data type: {:?}", input),
+            Yaml::Integer(i) => { Ok(vec![ usize::try_from(*i).map_err(|_| invalid_length(i))? ]) }
+            Yaml::Array(arr) => { arr.iter()
+                                     .map(|elem| elem.as_i64()
+                                                     .and_then(|int| usize::try_from(int).ok())
+                                                     .ok_or_else(|| invalid_length(elem)))
+                                     .collect()
+                                },
+            Yaml::Null       => { Ok(vec![]) }
+            _ => Err(Error::Logic(format!("Unexpected data type: {:?}", input))),
+        }
+    }
+
+    fn _parse_run_token(token: &str, line_length: usize) -> Result<Vec<usize>, Error> {
+        // parses a single run length, or a run-length encoded repetition of one: "3x4" stands for
+        // four runs of length 3, i.e. "3 3 3 3". a repetition needs a non-zero length and count, and
+        // can't repeat a run more times than there are squares in the line.
+        let parse = |value: &str, what: &str| value.parse::<usize>().map_err(|_| Error::Logic(format!(
+            "Unexpected run {}: {:?}", what, token)));
+        match token.split_once('x') {
+            Some((length, count)) => {
+                let (length, count) = (parse(length, "length")?, parse(count, "count")?);
+                if length == 0 || count == 0 {
+                    return Err(Error::Logic(format!("Run length and count must be non-zero: {:?}", token)));
+                }
+                if count > line_length {
+                    return Err(Error::Logic(format!(
+                        "Cannot repeat a run {} times in a line of {} squares: {:?}", count, line_length, token)));
+                }
+                Ok(vec![length; count])
+            },
+            None => Ok(vec![parse(token, "length")?]),
         }
     }
 
-    pub fn get_square(&self, x: usize, y: usize) -> Ref<Square> {
-        let grid = self.grid.borrow();
-        Ref::map(grid, |g| g.get_square(x, y))
+    pub fn get_square(&self, x: usize, y: usize) -> MappedRwLockReadGuard<'_, Square> {
+        let grid = self.grid.read();
+        RwLockReadGuard::map(grid, |g| g.get_square(x, y))
     }
-    pub fn get_square_mut(&self, x: usize, y: usize) -> RefMut<Square> {
-        let grid = self.grid.borrow_mut();
-        RefMut::map(grid, |g| g.get_square_mut(x, y))
+    pub fn get_square_mut(&self, x: usize, y: usize) -> MappedRwLockWriteGuard<'_, Square> {
+        let grid = self.grid.write();
+        RwLockWriteGuard::map(grid, |g| g.get_square_mut(x, y))
+    }
+    pub fn status_matrix(&self) -> Vec<Vec<SquareStatus>> {
+        // owned snapshot of the status of every square, indexed as [y][x]; see Grid::status_matrix
+        self.grid.read().status_matrix()
     }
     pub fn get_row(&self, direction: Direction, index: usize) -> &Row {
         match direction {
@@ -184,14 +902,157 @@ impl Puzzle {
             Vertical   => &mut self.cols[index],
         }
     }
-    fn apply_change(&mut self, change: Change) -> Result<Option<Change>, Error> {
+    pub fn apply_ascii_state(&mut self, art: &str) -> Result<Changes, Error> {
+        // sets the status of squares in the grid according to an ASCII art drawing of the grid,
+        // one line per row and one character per square: '#' for filled in squares, 'x' (or ' ')
+        // for crossed out squares and '.' for unknown squares.
+        let lines = art.lines().collect::<Vec<_>>();
+        if lines.len() != self.height() {
+            return Err(Error::Logic(format!(
+                "ASCII grid state has {} lines, but puzzle has {} rows", lines.len(), self.height())));
+        }
+        let mut changes = Vec::<Change>::new();
+        for (y, line) in lines.iter().enumerate() {
+            let chars = line.chars().collect::<Vec<_>>();
+            if chars.len() != self.width() {
+                return Err(Error::Logic(format!(
+                    "ASCII grid state line {} has {} squares, but puzzle has {} columns", y, chars.len(), self.width())));
+            }
+            for (x, &c) in chars.iter().enumerate() {
+                let status = match c {
+                    '#'             => SquareStatus::FilledIn,
+                    'x' | 'X' | ' ' => SquareStatus::CrossedOut,
+                    '.'             => continue,
+                    _ => return Err(Error::Logic(format!(
+                             "Unexpected character '{}' in ASCII grid state at line {}, column {}", c, y, x))),
+                };
+                if let Some(change) = self.get_square_mut(x, y).set_status(status)? {
+                    changes.push(Change::from(change));
+                }
+            }
+        }
+        Ok(changes)
+    }
+    pub fn apply_change(&mut self, change: Change) -> Result<Option<Change>, Error> {
         let mut square = self.get_square_mut(change.get_col(), change.get_row());
         square.apply_change(change)
     }
+    pub fn apply_replay(&mut self, replay: &str) -> Result<(), Error> {
+        // re-applies the changes from a replay produced by Solver::export_replay, in order.
+        // the direction and index at the start of each line are informational only; the changes themselves
+        // carry the location of the square they apply to.
+        for (line_nr, line) in replay.lines().enumerate() {
+            let mut tokens = line.split_whitespace();
+            let header = match tokens.next() {
+                Some(header) => header,
+                None         => continue, // blank line
+            };
+            let fields = header.split(':').collect::<Vec<_>>();
+            if fields.len() != 2 {
+                return Err(Error::Logic(format!("Invalid line header '{}' at line {} of replay", header, line_nr+1)));
+            }
+            parse_replay_direction(fields[0])?;
+            parse_replay_number(fields[1])?;
+
+            for token in tokens {
+                let fields = token.split(':').collect::<Vec<_>>();
+                let change = match (fields[0], fields.len()) {
+                    ("s", 5) => Change::from(StatusChange::new(
+                                    parse_replay_number(fields[1])?,
+                                    parse_replay_number(fields[2])?,
+                                    parse_replay_status(fields[3])?,
+                                    parse_replay_status(fields[4])?)),
+                    ("r", 6) => Change::from(RunChange::new(
+                                    parse_replay_number(fields[1])?,
+                                    parse_replay_number(fields[2])?,
+                                    parse_replay_direction(fields[3])?,
+                                    match fields[4] {
+                                        "-" => None,
+                                        x   => Some(parse_replay_number(x)?),
+                                    },
+                                    parse_replay_number(fields[5])?)),
+                    _ => return Err(Error::Logic(format!("Invalid change '{}' at line {} of replay", token, line_nr+1))),
+                };
+                if change.get_row() >= self.height() || change.get_col() >= self.width() {
+                    return Err(Error::Logic(format!(
+                        "Change '{}' at line {} of replay is outside of the puzzle", token, line_nr+1)));
+                }
+                self.apply_change(change)?;
+            }
+        }
+        Ok(())
+    }
     pub fn is_completed(&self) -> bool {
         self.rows.iter().all(|r| r.is_completed()) &&
             self.cols.iter().all(|c| c.is_completed())
     }
+    pub fn verify_solution(&self) -> Result<(), Vec<(Direction, usize)>> {
+        // checks the filled in squares of every row and column against their clues, independently
+        // of the solver's completion state; returns the rows and columns that don't match, if any.
+        let mismatches = self.rows.iter().chain(self.cols.iter())
+                                  .filter(|row| !row.matches_clues())
+                                  .map(|row| (row.direction, row.index))
+                                  .collect::<Vec<_>>();
+        match mismatches.is_empty() {
+            true  => Ok(()),
+            false => Err(mismatches),
+        }
+    }
+    pub fn check_against(&self, solution: &Grid) -> Result<Vec<(usize, usize)>, Error> {
+        // compares the known squares against a reference solution of the same size, and returns the (x, y)
+        // locations of the ones that disagree with it, in row-major order. unknown squares are never wrong,
+        // and neither is anything where the reference itself is unknown.
+        Ok(self.grid.read()
+                    .diff(solution)?
+                    .into_iter()
+                    .filter(|&(_, _, ours, theirs)| ours != SquareStatus::Unknown && theirs != SquareStatus::Unknown)
+                    .map(|(x, y, _, _)| (x, y))
+                    .collect())
+    }
+    pub fn num_known_squares(&self) -> usize {
+        // number of squares whose status is no longer unknown
+        let grid = self.grid.read();
+        grid.squares.iter()
+                    .map(|row| row.iter().filter(|sq| sq.get_status() != SquareStatus::Unknown).count())
+                    .sum()
+    }
+    pub fn guess_candidates(&self) -> Vec<(usize, usize)> {
+        // the unknown squares that are the most promising to guess on, i.e. those whose row and column together
+        // have the most known squares (so that a guess is most likely to lead to a conflict or to more deductions)
+        let grid = self.grid.read();
+        let known_in_row = (0..self.height()).map(|y| (0..self.width()).filter(|&x| grid.get_square(x, y).get_status() != SquareStatus::Unknown).count())
+                                             .collect::<Vec<_>>();
+        let known_in_col = (0..self.width()).map(|x| (0..self.height()).filter(|&y| grid.get_square(x, y).get_status() != SquareStatus::Unknown).count())
+                                            .collect::<Vec<_>>();
+        drop(grid);
+        let unknown_squares = self.unknown_squares();
+        let best_score = match unknown_squares.iter().map(|&(x, y)| known_in_row[y] + known_in_col[x]).max() {
+            Some(score) => score,
+            None        => return vec![],
+        };
+        unknown_squares.into_iter()
+                       .filter(|&(x, y)| known_in_row[y] + known_in_col[x] == best_score)
+                       .collect()
+    }
+    pub fn unknown_squares(&self) -> Vec<(usize, usize)> {
+        // coordinates (x, y) of all squares whose status is still unknown, in row-major order
+        let grid = self.grid.read();
+        grid.squares.iter()
+                    .flat_map(|row| row.iter())
+                    .filter(|sq| sq.get_status() == SquareStatus::Unknown)
+                    .map(|sq| (sq.get_col(), sq.get_row()))
+                    .collect()
+    }
+    pub fn completion_ratio(&self) -> f64 {
+        // fraction of squares in the grid whose status is known, between 0.0 and 1.0
+        (self.num_known_squares() as f64) / ((self.width() * self.height()) as f64)
+    }
+    pub fn fill_density(&self) -> f64 {
+        // fraction of squares in the grid that are filled in once the puzzle is solved, between 0.0 and 1.0.
+        // follows from the clues alone, so it can be used to categorize puzzles before solving them.
+        let num_filled: usize = self.rows.iter().map(|row| row.clue_total()).sum();
+        (num_filled as f64) / ((self.width() * self.height()) as f64)
+    }
 }
 
 impl Puzzle {
@@ -202,10 +1063,11 @@ impl Puzzle {
         result.push_str("run possible placements:\n");
         for row in self.rows.iter().chain(self.cols.iter()) {
             if row.is_trivially_empty() { continue; }
-            result.push_str(&format!("  {:-10} row {:2}:\n", row.direction, row.index));
-            for run in &row.runs {
+            result.push_str(&format!("  {:-10} row {:2} (clue total {}, min length {} of {}):\n",
+                row.direction, row.index, row.clue_total(), row.min_length(), row.length));
+            for run in row.runs() {
                 result.push_str(&format!("    run {:2} (len {}): {}\n", run.index, run.length,
-                    run.possible_placements.iter()
+                    run.placements().iter()
                                            .map(|range| format!("[{},{}]", range.start, range.end-1))
                                            .collect::<Vec<_>>()
                                            .join(", ")));
@@ -213,7 +1075,7 @@ impl Puzzle {
         }
 
         result.push_str("run assignment overview:\n");
-        let grid = self.grid.borrow();
+        let grid = self.grid.read();
         for y in 0..self.height() {
             for x in 0..self.width() {
                 let square: &Square = grid.get_square(x, y);
@@ -229,32 +1091,124 @@ impl Puzzle {
         result
     }
 
+    pub fn fmt_solution(&self) -> String {
+        // bare one-character-per-square rendering of the grid, without clues or borders;
+        // filled in squares are shown as '#', crossed out ones as ' ' and unknown ones as '.'
+        self._fmt_squares(' ')
+    }
+    pub fn ascii_state(&self) -> String {
+        // same as fmt_solution, but with crossed out squares shown as 'x' so that the result survives
+        // editors stripping trailing whitespace; can be read back in with apply_ascii_state.
+        self._fmt_squares('x')
+    }
+    fn _fmt_squares(&self, crossed_out: char) -> String {
+        let mut result = String::new();
+        let grid = self.grid.read();
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                result.push(match grid.get_square(x, y).get_status() {
+                    SquareStatus::FilledIn   => '#',
+                    SquareStatus::CrossedOut => crossed_out,
+                    SquareStatus::Unknown    => '.',
+                });
+            }
+            result.push('\n');
+        }
+        result
+    }
+
+    pub fn goal_string(&self) -> Option<String> {
+        // the solution as a row-major string of '1's (filled in) and '0's (crossed out), as used
+        // on the goal line of the .non format. only available once the puzzle has been solved.
+        if !self.is_completed() {
+            return None;
+        }
+        let grid = self.grid.read();
+        Some(grid.squares.iter()
+                         .flat_map(|row| row.iter())
+                         .map(|sq| match sq.get_status() {
+                             SquareStatus::FilledIn => '1',
+                             _                      => '0',
+                         })
+                         .collect())
+    }
+
+    pub fn placements_json(&self) -> String {
+        // the same run placement information as in dump_state, but as JSON: a list of rows (both horizontal
+        // and vertical), each with the list of its runs and their possible placements as [start, end] pairs
+        // (both inclusive).
+        let rows_json = self.rows.iter().chain(self.cols.iter()).map(|row| {
+            let runs_json = row.runs().iter().map(|run| {
+                format!("{{\"index\":{},\"length\":{},\"completed\":{},\"placements\":[{}]}}",
+                    run.index, run.length, run.is_completed(),
+                    run.placements().iter()
+                                           .map(|range| format!("[{},{}]", range.start, range.end-1))
+                                           .collect::<Vec<_>>()
+                                           .join(","))
+            }).collect::<Vec<_>>();
+            format!("{{\"direction\":\"{}\",\"index\":{},\"runs\":[{}]}}",
+                row.direction, row.index, runs_json.join(","))
+        }).collect::<Vec<_>>();
+        format!("[{}]", rows_json.join(","))
+    }
+
     // helper functions for Puzzle::fmt
-    pub fn _fmt(&self, subdivision: Option<usize>, emit_color: bool)
+    pub fn _fmt(&self, options: &RenderOptions)
         -> String
     {
-        // if subdivision is given, insert visual subdivisor lines across the grid every Nth row/col
-        let row_prefixes: Vec<Vec<ANSIString>> =
-            self.rows.iter()
-                     .map(|row| row.runs.iter()
-                                        .map(|run| run.to_colored_string())
-                                        .collect::<Vec<_>>())
-                     .collect();
+        // if subdivision is given, insert visual subdivisor lines across the grid every Nth row and every Mth col.
+        // in compact mode, squares are only as wide as the widest column label and there are no subdivisor lines.
+        // the rows and columns are labeled with their clues, or with their indices in coordinates mode.
+        let emit_color = options.emit_color;
+        let (row_subdivision, col_subdivision) = if options.compact { (None, None) } else { options.subdivision };
+        let labels = |rows: &Vec<Row>| -> Vec<Vec<ANSIString>> {
+            rows.iter()
+                .map(|row| match options.coordinates {
+                    true  => vec![ANSIString::from(row.index.to_string())],
+                    false => row.runs().iter()
+                                       .map(|run| run.to_colored_string())
+                                       .collect::<Vec<_>>(),
+                })
+                .collect()
+        };
+        let row_prefixes = labels(&self.rows);
+        let col_labels = match options.coordinates && options.compact {
+            // compact columns are too narrow to tell multi-digit indices apart, so write their digits top to bottom
+            true  => self.cols.iter()
+                              .map(|col| col.index.to_string().chars()
+                                                              .map(|digit| ANSIString::from(digit.to_string()))
+                                                              .collect::<Vec<_>>())
+                              .collect::<Vec<_>>(),
+            false => labels(&self.cols),
+        };
+        // cells are as wide as the widest column label, plus one space of padding in regular mode
+        let max_label_width = col_labels.iter()
+                                        .flatten()
+                                        .map(|label| label.len())
+                                        .max().unwrap_or(1);
+        let cell_width = match options.compact {
+            true  => max_label_width,
+            false => usize::max(3, max_label_width + 1),
+        };
 
         let prefix_len = row_prefixes.iter()
                                      .map(|parts| parts.iter()
-                                                       .fold(0, |sum, ansi_str| sum + ansi_str.len() + 1) // note: .len() returns length WITHOUT ansi color escape sequences
-                                                  -1) // minus one at the end to match the length of a join(" ")
+                                                       .map(|ansi_str| ansi_str.len()) // note: .len() returns length WITHOUT ansi color escape sequences
+                                                       .sum::<usize>()
+                                                  + parts.len().saturating_sub(1)) // plus the spaces of a join(" "), if any
                                      .max().unwrap();
-        let max_col_runs = self.cols.iter()
-                                    .map(|col| col.runs.len())
-                                    .max().unwrap();
+        let max_col_labels = col_labels.iter()
+                                       .map(|labels| labels.len())
+                                       .max().unwrap();
 
         let mut result = String::new();
-        let grid = self.grid.borrow();
+        let grid = self.grid.read();
 
-        for i in (0..max_col_runs).rev() {
-            result.push_str(&self._fmt_header(i, prefix_len, subdivision, emit_color));
+        if let Some(name) = &self.name {
+            result.push_str(&format!("{}\n", name));
+        }
+        for i in (0..max_col_labels).rev() {
+            result.push_str(&Self::_fmt_header(&col_labels, i, prefix_len, col_subdivision, cell_width, options.compact, emit_color));
         }
 
         // top board line
@@ -263,8 +1217,8 @@ impl Puzzle {
             "\u{2554}",
             "\u{2557}",
             "\u{2564}",
-            subdivision,
-            &(0..self.width()).map(|_| String::from("\u{2550}\u{2550}\u{2550}"))
+            col_subdivision,
+            &(0..self.width()).map(|_| "\u{2550}".repeat(cell_width))
                               .collect::<Vec<_>>(),
             emit_color,
         ));
@@ -276,23 +1230,26 @@ impl Puzzle {
                 "\u{2551}",
                 "\u{2551}",
                 "\u{2502}",
-                subdivision,
+                col_subdivision,
                 &grid.squares[y].iter()
-                                .map(|s| format!(" {:1} ", s))
+                                .map(|s| match options.compact {
+                                    true  => format!("{:<1$}", s.fmt_visual(), cell_width),
+                                    false => format!(" {:<1$}", s.fmt_visual(), cell_width - 1),
+                                })
                                 .collect::<Vec<_>>(),
                 emit_color,
             ));
 
             // horizontal subdivisor line
-            if let Some(subdiv) = subdivision {
+            if let Some(subdiv) = row_subdivision {
                 if ((y+1) % subdiv == 0) && (y != self.height()-1) {
                     result.push_str(&Self::_fmt_line(
                         &ralign("", prefix_len),
                         "\u{255F}",
                         "\u{2562}",
                         "\u{253C}",
-                        subdivision,
-                        &(0..self.width()).map(|_| String::from("\u{2500}\u{2500}\u{2500}"))
+                        col_subdivision,
+                        &(0..self.width()).map(|_| "\u{2500}".repeat(cell_width))
                                           .collect::<Vec<_>>(),
                         emit_color,
                     ));
@@ -305,8 +1262,8 @@ impl Puzzle {
             "\u{255A}",
             "\u{255D}",
             "\u{2567}",
-            subdivision,
-            &(0..self.width()).map(|_| String::from("\u{2550}\u{2550}\u{2550}"))
+            col_subdivision,
+            &(0..self.width()).map(|_| "\u{2550}".repeat(cell_width))
                               .collect::<Vec<_>>(),
             emit_color,
         ));
@@ -336,20 +1293,99 @@ impl Puzzle {
         return result;
     }
 
-    fn _fmt_header(&self, line_idx: usize,
-                          prefix_len: usize,
-                          subdivision: Option<usize>,
-                          emit_color: bool)
+    pub fn render_svg(&self, options: &RenderOptions) -> String {
+        // renders the puzzle as an SVG document: the grid with its filled in and crossed out squares,
+        // with the row clues to its left and the column clues above it. same layout as the text board:
+        // row clues are right-aligned against the grid, column clues are bottom-aligned against it,
+        // and subdivision lines are drawn thicker every Nth row and every Mth col.
+        let cell = SVG_CELL_SIZE;
+        let margin = cell / 2;
+        let max_row_runs = self.rows.iter().map(|row| row.runs().len()).max().unwrap_or(0);
+        let max_col_runs = self.cols.iter().map(|col| col.runs().len()).max().unwrap_or(0);
+        let grid_x = margin + max_row_runs * cell; // top left corner of the grid
+        let grid_y = margin + max_col_runs * cell;
+        let width = grid_x + self.width() * cell + margin;
+        let height = grid_y + self.height() * cell + margin;
+
+        let mut result = String::new();
+        result.push_str(&format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n", width, height));
+        if let Some(name) = &self.name {
+            result.push_str(&format!("<title>{}</title>\n", xml_escape(name)));
+        }
+        result.push_str(&format!("<rect width=\"{}\" height=\"{}\" fill=\"white\"/>\n", width, height));
+
+        // clues
+        result.push_str("<g font-family=\"sans-serif\" font-size=\"12\" text-anchor=\"middle\" dominant-baseline=\"central\">\n");
+        for row in &self.rows {
+            let num_runs = row.runs().len();
+            for (j, run) in row.runs().iter().enumerate() {
+                result.push_str(&format!("<text x=\"{}\" y=\"{}\">{}</text>\n",
+                    grid_x - (num_runs - j) * cell + cell / 2, grid_y + row.index * cell + cell / 2, run.length));
+            }
+        }
+        for col in &self.cols {
+            let num_runs = col.runs().len();
+            for (j, run) in col.runs().iter().enumerate() {
+                result.push_str(&format!("<text x=\"{}\" y=\"{}\">{}</text>\n",
+                    grid_x + col.index * cell + cell / 2, grid_y - (num_runs - j) * cell + cell / 2, run.length));
+            }
+        }
+        result.push_str("</g>\n");
+
+        // squares
+        let grid = self.grid.read();
+        let inset = cell / 4;
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let (left, top) = (grid_x + x * cell, grid_y + y * cell);
+                match grid.get_square(x, y).get_status() {
+                    SquareStatus::FilledIn => result.push_str(&format!(
+                        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"black\"/>\n", left, top, cell, cell)),
+                    SquareStatus::CrossedOut => result.push_str(&format!(
+                        "<path d=\"M{0},{1} L{2},{3} M{2},{1} L{0},{3}\" stroke=\"gray\" stroke-width=\"1.5\"/>\n",
+                        left + inset, top + inset, left + cell - inset, top + cell - inset)),
+                    SquareStatus::Unknown => {},
+                }
+            }
+        }
+
+        // grid lines; the outer border and subdivision lines are drawn thicker
+        let stroke_width = |i: usize, n: usize, subdivision: Option<usize>| {
+            let is_subdivision = matches!(subdivision, Some(s) if i.is_multiple_of(s));
+            if i == 0 || i == n || is_subdivision { 2 } else { 1 }
+        };
+        for x in 0..=self.width() {
+            result.push_str(&format!("<line x1=\"{0}\" y1=\"{1}\" x2=\"{0}\" y2=\"{2}\" stroke=\"black\" stroke-width=\"{3}\"/>\n",
+                grid_x + x * cell, grid_y, grid_y + self.height() * cell, stroke_width(x, self.width(), options.subdivision.1)));
+        }
+        for y in 0..=self.height() {
+            result.push_str(&format!("<line x1=\"{1}\" y1=\"{0}\" x2=\"{2}\" y2=\"{0}\" stroke=\"black\" stroke-width=\"{3}\"/>\n",
+                grid_y + y * cell, grid_x, grid_x + self.width() * cell, stroke_width(y, self.height(), options.subdivision.0)));
+        }
+        result.push_str("</svg>\n");
+        result
+    }
+    fn _fmt_header(col_labels: &[Vec<ANSIString>],
+                   line_idx: usize,
+                   prefix_len: usize,
+                   subdivision: Option<usize>,
+                   cell_width: usize,
+                   compact: bool,
+                   emit_color: bool)
         -> String
     {
+        // line_idx counts upwards from the line right above the board; each column's labels are bottom-aligned.
+        // note: regular squares have the label left-aligned after one space of padding;
+        // compact squares have no padding.
+        let padding = if compact { "" } else { " " };
         let mut content_parts = Vec::<String>::new();
-        for col in &self.cols {
+        for labels in col_labels {
             let part: String;
-            if line_idx < col.runs.len() {
-                let colored = col.runs[col.runs.len()-1-line_idx].to_colored_string();
-                part = format!(" {}", lalign_colored(&colored, 2, emit_color));
+            if line_idx < labels.len() {
+                let colored = &labels[labels.len()-1-line_idx];
+                part = format!("{}{}", padding, lalign_colored(colored, cell_width - padding.len(), emit_color));
             } else {
-                part = format!(" {:-2}", " ");
+                part = " ".repeat(cell_width);
             }
 
             content_parts.push(part);
@@ -368,23 +1404,331 @@ impl Puzzle {
 }
 impl fmt::Display for Puzzle {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let subdivision = Some(5);
-        write!(f, "{}", self._fmt(subdivision, false))
+        write!(f, "{}", self._fmt(&RenderOptions::default()))
     }
 }
 impl CloneGridAware for Puzzle {
-    fn clone_with_grid(&self, grid: &Rc<RefCell<Grid>>) -> Self {
+    fn clone_with_grid(&self, grid: &Arc<RwLock<Grid>>) -> Self {
         Puzzle {
             rows: self.rows.iter().map(|r| r.clone_with_grid(&grid)).collect(),
             cols: self.cols.iter().map(|c| c.clone_with_grid(&grid)).collect(),
-            grid: Rc::clone(grid),
+            grid: Arc::clone(grid),
+            name: self.name.clone(),
+            wrap: self.wrap,
+        }
+    }
+}
+impl Puzzle {
+    pub fn transpose(&self) -> Puzzle {
+        // returns a copy of this puzzle with rows and columns swapped, including the state of the grid.
+        // solving the transposed puzzle yields the transpose of this puzzle's solution.
+        let grid: Arc<RwLock<Grid>> = Arc::new(RwLock::new(self.grid.read().transpose()));
+        Puzzle {
+            rows: self.cols.iter().map(|c| c.clone_transposed(&grid)).collect(),
+            cols: self.rows.iter().map(|r| r.clone_transposed(&grid)).collect(),
+            grid,
+            name: self.name.clone(),
+            wrap: self.wrap,
         }
     }
 }
 impl Clone for Puzzle {
     fn clone(&self) -> Self {
-        let grid: Rc<RefCell<Grid>> = Rc::new(RefCell::new(self.grid.borrow().clone()));
+        let grid: Arc<RwLock<Grid>> = Arc::new(RwLock::new(self.grid.read().clone()));
         self.clone_with_grid(&grid)
     }
 }
 
+// ------------------------------------------------
+
+const _: fn() = || {
+    // puzzles and solvers can be moved to another thread, e.g. to solve a batch of puzzles in a worker pool,
+    // and the options to solve them with can be shared between those threads
+    fn assert_send<T: Send>() {}
+    fn assert_sync<T: Sync>() {}
+    assert_send::<Puzzle>();
+    assert_send::<Solver>();
+    assert_send::<SolveOptions>();
+    assert_sync::<SolveOptions>();
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_puzzle() -> Puzzle {
+        // solution:
+        //   ##.
+        //   .#.
+        //   ###
+        Puzzle::from_clues(vec![vec![2], vec![1], vec![3]],
+                           vec![vec![1, 1], vec![3], vec![1]]).unwrap()
+    }
+
+    #[test]
+    fn verify_solution_accepts_solution() {
+        let mut puzzle = small_puzzle();
+        puzzle.apply_ascii_state("##x\nx#x\n###").unwrap();
+        assert_eq!(puzzle.verify_solution(), Ok(()));
+    }
+
+    #[test]
+    fn verify_solution_reports_corrupted_square() {
+        // one square filled in that shouldn't be; both its row and its column no longer match their clues
+        let mut puzzle = small_puzzle();
+        puzzle.apply_ascii_state("##x\nx##\n###").unwrap();
+        assert_eq!(puzzle.verify_solution(), Err(vec![(Horizontal, 1), (Vertical, 2)]));
+    }
+
+    #[test]
+    fn malformed_clues_are_errors() {
+        for rows in &["[\"1 x2\"]", "[\"ax2\"]", "[\"2xb\"]", "[-1]", "[1.5]", "[[1, a]]", "[[-2]]",
+                      "[\"1x0\"]", "[\"0x1\"]", "[\"1x2\"]", "[\"1x18446744073709551615\"]"] {
+            let yaml = format!("rows: {}\ncols: [1]", rows);
+            assert!(matches!(Puzzle::all_from_yaml(&yaml), Err(Error::Logic(_))), "{}", yaml);
+        }
+        assert!(Puzzle::all_from_yaml("rows: [\"1x1\"]\ncols: [[1]]").is_ok());
+    }
+
+    #[test]
+    fn solver_can_finish_on_another_thread() {
+        // run a few solver iterations so that some runs have narrowed down placements or are completed,
+        // then hand the partially solved puzzle over to another thread and let it finish there
+        let mut solver = Solver::new(small_puzzle());
+        for _ in 0..3 {
+            solver.next().unwrap().unwrap();
+        }
+        let partial = solver.puzzle.ascii_state();
+        let solved = std::thread::spawn(move || {
+            for result in solver.by_ref() {
+                result.unwrap();
+            }
+            solver.puzzle
+        }).join().unwrap();
+        assert_ne!(solved.ascii_state(), partial);
+        assert!(solved.is_completed());
+        assert_eq!(solved.verify_solution(), Ok(()));
+    }
+
+    #[test]
+    fn clamp_edges_at_filled_corners() {
+        // solution:
+        //   ###.
+        //   #...
+        //   #...
+        //   ..##
+        let mut puzzle = Puzzle::from_clues(vec![vec![3], vec![1], vec![1], vec![2]],
+                                            vec![vec![3], vec![1], vec![1, 1], vec![1]]).unwrap();
+        puzzle.apply_ascii_state("#...\n....\n....\n...#").unwrap();
+        for &(direction, index) in &[(Horizontal, 0), (Vertical, 0), (Horizontal, 3), (Vertical, 3)] {
+            puzzle.get_row_mut(direction, index).clamp_edges().unwrap();
+        }
+        assert_eq!(puzzle.ascii_state(), "###x\n#...\n#..x\nxx##\n");
+    }
+
+    #[test]
+    fn next_speculative_retracts_failed_guess() {
+        // solution:
+        //   .##.
+        //   #...
+        //   #..#
+        // logic alone gets stuck, and filling in the top left square leads to a contradiction
+        let puzzle = Puzzle::from_clues(vec![vec![2], vec![1], vec![1, 1]],
+                                        vec![vec![2], vec![1], vec![1], vec![1]]).unwrap();
+        let mut solver = Solver::new(puzzle);
+        let expected_guess = Guess { x: 0, y: 0, status: SquareStatus::FilledIn };
+        let mut guessed = false;
+        let mut retracted = false;
+        while let Some(step) = solver.next_speculative(SquareStatus::FilledIn) {
+            match step.unwrap() {
+                SpeculativeStep::Guessed(guess) => {
+                    assert_eq!(guess, expected_guess);
+                    assert_eq!(solver.guesses().collect::<Vec<_>>(), vec![&expected_guess]);
+                    guessed = true;
+                },
+                SpeculativeStep::Retracted(guess) => {
+                    assert!(guessed);
+                    assert_eq!(guess, expected_guess);
+                    assert_eq!(solver.guesses().count(), 0);
+                    assert_eq!(solver.puzzle.get_square(0, 0).get_status(), SquareStatus::CrossedOut);
+                    retracted = true;
+                },
+                _ => {},
+            }
+        }
+        assert!(guessed && retracted);
+        assert_eq!(solver.puzzle.ascii_state(), "x##x\n#xxx\n#xx#\n");
+        assert_eq!(solver.puzzle.verify_solution(), Ok(()));
+    }
+
+    #[test]
+    fn retracted_guess_is_fed_back_into_solver() {
+        // same puzzle as above; once the guess on the top left square is retracted, that square is crossed out,
+        // and its row and column must be queued again with run placements that no longer cover it
+        let puzzle = Puzzle::from_clues(vec![vec![2], vec![1], vec![1, 1]],
+                                        vec![vec![2], vec![1], vec![1], vec![1]]).unwrap();
+        let mut solver = Solver::new(puzzle);
+        solver.strict = true; // recalculates the placements of the affected lines right away
+        while !matches!(solver.next_speculative(SquareStatus::FilledIn).unwrap().unwrap(), SpeculativeStep::Retracted(_)) {}
+        assert_eq!(solver.puzzle.get_square(0, 0).get_status(), SquareStatus::CrossedOut);
+        assert!(solver.queue.contains(&(Horizontal, 0)) && solver.queue.contains(&(Vertical, 0)));
+        for direction in [Horizontal, Vertical] {
+            for run in solver.puzzle.get_row(direction, 0).runs() {
+                assert!(run.placements().iter().all(|placement| !placement.contains(&0)),
+                        "{} run #{} can still be placed on the crossed out square: {:?}", direction, run.index, run.placements());
+            }
+        }
+    }
+
+    #[test]
+    fn history_is_only_recorded_on_request() {
+        let mut solver = Solver::new(small_puzzle());
+        for result in solver.by_ref() {
+            result.unwrap();
+        }
+        assert!(solver.history.is_empty());
+
+        let mut solver = Solver::new(small_puzzle());
+        solver.record_history = true;
+        for result in solver.by_ref() {
+            result.unwrap();
+        }
+        let mut replayed = small_puzzle();
+        replayed.apply_replay(&solver.export_replay()).unwrap();
+        assert!(!solver.history.is_empty());
+        assert_eq!(replayed.ascii_state(), solver.puzzle.ascii_state());
+        assert_eq!(replayed.verify_solution(), Ok(()));
+    }
+
+    fn solved_lines(row_clues: Vec<Vec<usize>>, col_clues: Vec<Vec<usize>>) -> Vec<Vec<char>> {
+        // the lines of the default rendering of the solved puzzle, as characters (the box drawing ones are multi-byte)
+        let mut solver = Solver::new(Puzzle::from_clues(row_clues, col_clues).unwrap());
+        for result in solver.by_ref() {
+            result.unwrap();
+        }
+        assert!(solver.puzzle.is_completed());
+        solver.puzzle._fmt(&RenderOptions::default()).lines().map(|line| line.chars().collect()).collect()
+    }
+
+    #[test]
+    fn fmt_aligns_multi_digit_clues() {
+        // solution (and its transpose, below):
+        //   ###############
+        //   ############...
+        //   #.............#
+        let row_clues = vec![vec![15], vec![12], vec![1, 1]];
+        let col_clues = [vec![vec![3]], vec![vec![2]; 11], vec![vec![1], vec![1], vec![1, 1]]].concat();
+
+        // multi-digit row clues are right-aligned against the grid
+        let lines = solved_lines(row_clues.clone(), col_clues.clone());
+        let border = lines[2].iter().position(|&c| c == '\u{2554}').unwrap();
+        let prefixes = lines[3..6].iter().map(|line| line[..border].iter().collect::<String>()).collect::<Vec<_>>();
+        assert_eq!(prefixes, vec![" 15 ", " 12 ", "1 1 "]);
+
+        // multi-digit column clues start right above the squares of their column, without running into each other
+        let lines = solved_lines(col_clues, row_clues);
+        let squares = lines[3].iter().enumerate().filter(|&(_, &c)| c == '\u{25A0}').map(|(at, _)| at).collect::<Vec<_>>();
+        assert_eq!(squares.len(), 3);
+        for (&at, label) in squares.iter().zip(&["15", "12", "1"]) {
+            assert_eq!(lines[1][at..at + label.len()].iter().collect::<String>(), *label);
+            assert_eq!(lines[1][at - 1], ' ');
+        }
+        assert_eq!(lines[0][squares[2]], '1'); // the last column's first clue goes on top
+        assert!(lines.iter().all(|line| line.len() == lines[2].len()));
+    }
+
+    #[test]
+    fn fmt_wide_puzzle_lines_have_equal_width() {
+        // 5x60, with a long list of row clues, an empty row, and up to three clues per column
+        let filled = |x: usize, y: usize| match y {
+            0 => x.is_multiple_of(2),
+            1 => false,
+            2 => true,
+            3 => x.is_multiple_of(3),
+            _ => x % 3 != 2,
+        };
+        let pixels = (0..5).map(|y| (0..60).map(|x| filled(x, y)).collect())
+                           .collect::<Vec<Vec<bool>>>();
+        let puzzle = Puzzle::from_pixels(&pixels).unwrap();
+        assert!(puzzle.rows[1].runs().is_empty());
+        for compact in [false, true] {
+            let options = RenderOptions { compact, ..RenderOptions::default() };
+            let rendered = puzzle._fmt(&options);
+            let widths = rendered.lines().map(|line| line.chars().count()).collect::<Vec<_>>();
+            assert!(widths.iter().all(|&width| width == widths[0]), "compact={}: {:?}\n{}", compact, widths, rendered);
+        }
+    }
+
+    #[test]
+    fn clone_gets_its_own_grid() {
+        let original = small_puzzle();
+        let copy = original.clone();
+        assert!(!Arc::ptr_eq(&original.grid, &copy.grid));
+        for row in copy.rows.iter().chain(copy.cols.iter()) {
+            assert!(Arc::ptr_eq(&row.grid, &copy.grid));
+        }
+
+        // changes to the copy, through its grid or through its rows, don't show up in the original
+        copy.get_square_mut(0, 0).set_status(SquareStatus::FilledIn).unwrap();
+        copy.get_row(Vertical, 2).get_square_mut(1).set_status(SquareStatus::CrossedOut).unwrap();
+        assert_eq!(original.get_square(0, 0).get_status(), SquareStatus::Unknown);
+        assert_eq!(original.get_square(2, 1).get_status(), SquareStatus::Unknown);
+        assert_eq!(copy.get_square(2, 1).get_status(), SquareStatus::CrossedOut);
+    }
+
+    #[test]
+    fn solving_the_transpose_gives_the_transposed_solution() {
+        // for a batch of random pictures that can be solved by logic alone, solving the transposed puzzle
+        // must yield the transpose of the original solution
+        let solve = |puzzle: Puzzle| {
+            let mut solver = Solver::new(puzzle);
+            for result in solver.by_ref() {
+                result.unwrap();
+            }
+            solver.puzzle
+        };
+        let mut rng = Rng::new(588);
+        let mut solved_count = 0;
+        for _ in 0..50 {
+            let (width, height) = (3 + rng.gen_below(8), 3 + rng.gen_below(8));
+            let pixels = (0..height).map(|_| (0..width).map(|_| rng.gen_below(5) < 3).collect())
+                                    .collect::<Vec<Vec<bool>>>();
+            let puzzle = Puzzle::from_pixels(&pixels).unwrap();
+            let transposed = puzzle.transpose();
+
+            // the rows of the transpose are the original columns and vice versa, running in the other direction
+            for (line, original) in transposed.rows.iter().chain(transposed.cols.iter())
+                                              .zip(puzzle.cols.iter().chain(puzzle.rows.iter()))
+            {
+                assert_eq!(line.direction, original.direction.orthogonal());
+                assert!(line.runs().iter().all(|run| run.direction == line.direction));
+                assert_eq!(line.runs().iter().map(|run| run.length).collect::<Vec<_>>(),
+                           original.runs().iter().map(|run| run.length).collect::<Vec<_>>());
+            }
+
+            let solved = solve(puzzle);
+            if !solved.is_completed() { continue; }
+            let solved_transposed = solve(transposed);
+            assert!(solved_transposed.is_completed());
+            assert_eq!(solved_transposed.ascii_state(), solved.transpose().ascii_state());
+            solved_count += 1;
+        }
+        assert!(solved_count >= 10, "only {} of the random puzzles could be solved by logic alone", solved_count);
+    }
+
+    #[test]
+    fn prefill_empty_lines_crosses_out_empty_clues() {
+        // solution:
+        //   #.#
+        //   ...
+        //   #..
+        let mut puzzle = Puzzle::from_clues(vec![vec![1, 1], vec![0], vec![1]],
+                                            vec![vec![1, 1], vec![], vec![1]]).unwrap();
+        let changes = puzzle.prefill_empty_lines();
+        // row 1 and column 1 share a square, which only gets crossed out once
+        assert_eq!(changes.len(), 5);
+        assert!(puzzle.rows[1].is_completed() && puzzle.cols[1].is_completed());
+        assert_eq!(puzzle.ascii_state(), ".x.\nxxx\n.x.\n");
+        assert!(puzzle.prefill_empty_lines().is_empty());
+    }
+}
@@ -1,24 +1,26 @@
 // vim: set ai et ts=4 sw=4 sts=4:
 //use std::iter::Iterator;
 mod solver;
+mod strategy;
+pub use self::solver::{solve_line, solve_line_wrapped};
+pub use self::strategy::{LineStrategy, CheckCompletedRuns, CheckCompleted, ClampEdges, UpdatePossibleRunPlacements, FillOverlap,
+                         ExtendFromWalls, PackTightFields, InferRunAssignments, InferStatusAssignments, CrossForcedGaps,
+                         SolveWrapped, default_strategies, overlap_strategies, wrapped_strategies};
 
 use std::fmt;
 use std::ops::Range;
-use std::convert::{TryInto, TryFrom};
-use std::cmp::{min, max};
-use std::rc::{Rc, Weak};
-use std::cell::{Ref, RefMut, RefCell};
-use std::collections::HashSet;
+use std::sync::Arc;
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard, MappedRwLockReadGuard, MappedRwLockWriteGuard};
 use ansi_term::{Colour, Style, ANSIString};
 
 use super::util::{Direction, Direction::*};
-use super::grid::{Grid, Square, SquareStatus::{CrossedOut, FilledIn}, Change, Changes, Error, CloneGridAware};
+use super::grid::{Grid, Square, SquareStatus, SquareStatus::{CrossedOut, FilledIn, Unknown}, Change, Changes, Error, CloneGridAware};
 
 pub trait DirectionalSequence
 {
     fn get_row_index(&self) -> usize;
     fn get_direction(&self) -> Direction;
-    fn get_grid(&self) -> &Rc<RefCell<Grid>>;
+    fn get_grid(&self) -> &Arc<RwLock<Grid>>;
 
     fn square_index(&self, at: usize) -> (usize, usize) {
         match self.get_direction() {
@@ -26,15 +28,19 @@ pub trait DirectionalSequence
             Vertical   => (self.get_row_index(), at),
         }
     }
-    fn get_square(&self, index: usize) -> Ref<Square> {
-        let grid = self.get_grid().borrow();
+    fn get_square(&self, index: usize) -> MappedRwLockReadGuard<'_, Square> {
+        let grid = self.get_grid().read();
         let (x,y) = self.square_index(index);
-        Ref::map(grid, |g| g.get_square(x, y))
+        RwLockReadGuard::map(grid, |g| g.get_square(x, y))
     }
-    fn get_square_mut(&self, index: usize) -> RefMut<Square> {
-        let grid = self.get_grid().borrow_mut();
+    fn get_square_mut(&self, index: usize) -> MappedRwLockWriteGuard<'_, Square> {
         let (x,y) = self.square_index(index);
-        RefMut::map(grid, |g| g.get_square_mut(x, y))
+        // any outstanding square borrow at this point is a bug (the lock isn't reentrant, so it would deadlock);
+        // report which square was being accessed instead, to make these easier to track down.
+        debug_assert!(self.get_grid().try_write().is_some(),
+            "grid already borrowed while accessing square (x={}, y={}) mutably", x, y);
+        let grid = self.get_grid().write();
+        RwLockWriteGuard::map(grid, |g| g.get_square_mut(x, y))
     }
 }
 
@@ -43,20 +49,20 @@ pub struct Row {
     pub direction:  Direction,
     pub index:      usize,
     pub length:     usize,
-    pub runs:       Vec<Run>,
-    pub grid:       Rc<RefCell<Grid>>,
+    runs:           Vec<Run>,
+    pub grid:       Arc<RwLock<Grid>>,
     pub completed:  bool,
 }
 
 impl Row {
-    pub fn new(grid: &Rc<RefCell<Grid>>,
+    pub fn new(grid: &Arc<RwLock<Grid>>,
                direction: Direction,
                row_index: usize,
                run_lengths: &Vec<usize>) -> Self
     {
         let row_length = match direction {
-            Horizontal => grid.borrow().width(),
-            Vertical   => grid.borrow().height(),
+            Horizontal => grid.read().width(),
+            Vertical   => grid.read().height(),
         };
         let runs = run_lengths.iter()
                               .enumerate()
@@ -67,12 +73,27 @@ impl Row {
             index:     row_index,
             length:    row_length,
             runs:      runs,
-            grid:      Rc::clone(grid),
+            grid:      Arc::clone(grid),
             completed: false,
         }
     }
+    pub fn from_state(clues: &[usize], cells: &[SquareStatus]) -> Result<Self, Error>
+    {
+        // builds a standalone horizontal row on a grid of its own, with the given run lengths and
+        // square statuses, and with the possible placements of its runs already worked out.
+        // useful for examining the row solver's reasoning about a single line in isolation.
+        let grid = Arc::new(RwLock::new(Grid::new(cells.len(), 1)?));
+        let mut row = Row::new(&grid, Horizontal, 0, &clues.to_vec());
+        for (at, &status) in cells.iter().enumerate() {
+            if status != Unknown {
+                row.get_square_mut(at).set_status(status)?;
+            }
+        }
+        row.update_possible_run_placements()?;
+        Ok(row)
+    }
     fn _ranges_of_squares<P>(&self, pred: P) -> Vec<Range<usize>>
-        where P: Fn(Ref<Square>, usize) -> bool
+        where P: Fn(MappedRwLockReadGuard<Square>, usize) -> bool
     {
         // given a predicate on a square, returns a set of mutually exclusive ranges within this row 
         // for which the predicate holds for all squares in that range.
@@ -134,6 +155,26 @@ impl Row {
     pub fn is_completed(&self) -> bool {
         self.completed
     }
+    pub fn runs(&self) -> &[Run] {
+        // this row's runs in clue order, i.e. left-to-right for horizontal rows and top-to-bottom for vertical ones
+        &self.runs
+    }
+    pub fn run_at(&self, pos: usize) -> Option<&Run> {
+        // the run that the square at the given position has been assigned to, if any
+        self.get_square(pos).get_run_index(self.direction).map(|idx| &self.runs[idx])
+    }
+    pub fn clue_total(&self) -> usize {
+        // total number of squares that are filled in once this row is solved
+        self.runs.iter().map(|run| run.length).sum()
+    }
+    pub fn min_length(&self) -> usize {
+        // minimum space needed to fit all runs: the sum of their lengths plus one square of separation between each of them
+        let non_empty_runs = self.runs.iter().filter(|run| run.length > 0).count();
+        self.clue_total() + non_empty_runs.saturating_sub(1)
+    }
+    pub fn is_feasible(&self) -> bool {
+        self.min_length() <= self.length
+    }
     pub fn is_trivially_empty(&self) -> bool {
         self.runs.is_empty() || self.runs.iter().all(|r| r.length == 0)
     }
@@ -146,6 +187,9 @@ impl Row {
         //  - the run must be at least as long as the sequence.
         //  - the run must contain a possible placement that contains ALL squares in the sequence
         //    (or equivalently: it must contain BOTH the start and end square in the sequence)
+        if seq.start >= seq.end {
+            return vec![]; // an empty sequence isn't part of any run
+        }
         self.runs.iter()
                  .filter(|run| run.length >= seq.len()
                                && run.possible_placements.iter()
@@ -157,22 +201,48 @@ impl Row {
     pub fn possible_runs_for_square(&self, position: usize) -> Vec<usize> {
         self.possible_runs_for_sequence(&(position..(position+1)))
     }
+    pub fn matches_clues(&self) -> bool {
+        // checks whether the sequences of filled in squares currently in this row are exactly
+        // the runs given by its clues, regardless of the solver's own bookkeeping
+        let actual_lengths = self._ranges_of_squares(|sq, _| sq.get_status() == FilledIn)
+                                 .iter()
+                                 .map(|range| range.len())
+                                 .collect::<Vec<_>>();
+        let expected_lengths = self.runs.iter()
+                                        .map(|run| run.length)
+                                        .filter(|&len| len > 0)
+                                        .collect::<Vec<_>>();
+        actual_lengths == expected_lengths
+    }
 }
 impl DirectionalSequence for Row {
     fn get_row_index(&self) -> usize { self.index }
     fn get_direction(&self) -> Direction { self.direction }
-    fn get_grid(&self)      -> &Rc<RefCell<Grid>> { &self.grid }
+    fn get_grid(&self)      -> &Arc<RwLock<Grid>> { &self.grid }
+}
+
+impl Row {
+    pub fn clone_transposed(&self, grid: &Arc<RwLock<Grid>>) -> Self {
+        // copy of this row as it appears in the transposed puzzle on the given grid; the row and its
+        // runs keep their index and placements, but run in the orthogonal direction.
+        let mut row = self.clone_with_grid(grid);
+        row.direction = row.direction.orthogonal();
+        for run in &mut row.runs {
+            run.direction = run.direction.orthogonal();
+        }
+        row
+    }
 }
 
 impl CloneGridAware for Row {
-    fn clone_with_grid(&self, grid: &Rc<RefCell<Grid>>) -> Self {
+    fn clone_with_grid(&self, grid: &Arc<RwLock<Grid>>) -> Self {
         Row {
             direction:    self.direction.clone(),
             index:        self.index.clone(),
             length:       self.length.clone(),
             completed:    self.completed.clone(),
             runs:         self.runs.iter().map(|run| run.clone_with_grid(grid)).collect(),
-            grid:         Rc::clone(grid),
+            grid:         Arc::clone(grid),
         }
     }
 }
@@ -186,13 +256,13 @@ pub struct Run {
     pub index: usize,
     pub row_index: usize,
     pub row_length: usize,
-    pub grid: Rc<RefCell<Grid>>,
-    pub possible_placements: Vec<Range<usize>>,
+    pub grid: Arc<RwLock<Grid>>,
+    possible_placements: Vec<Range<usize>>,
     completed: bool,
 }
 
 impl Run {
-    pub fn new(grid: &Rc<RefCell<Grid>>,
+    pub fn new(grid: &Arc<RwLock<Grid>>,
                direction: Direction,
                index: usize,
                row_index: usize,
@@ -205,7 +275,7 @@ impl Run {
             index,
             row_index,
             row_length,
-            grid: Rc::clone(grid),
+            grid: Arc::clone(grid),
             possible_placements: Vec::<Range<usize>>::new(),
             completed: false,
         }
@@ -240,12 +310,35 @@ impl Run {
     pub fn is_completed(&self) -> bool {
         self.completed
     }
+    pub fn placements(&self) -> &[Range<usize>] {
+        // the positions at which this run can still be placed, in left-to-right order
+        &self.possible_placements
+    }
+    pub fn min_start(&self) -> Option<usize> {
+        // starting position of the leftmost possible placement of this run, if there are any
+        self.possible_placements.first().map(|range| range.start)
+    }
+    pub fn max_start(&self) -> Option<usize> {
+        // starting position of the rightmost possible placement of this run, if there are any
+        self.possible_placements.last().map(|range| range.start)
+    }
+    pub fn slack(&self) -> usize {
+        // how far this run can still move around, i.e. the distance between its leftmost and rightmost start
+        match (self.min_start(), self.max_start()) {
+            (Some(min_start), Some(max_start)) => max_start - min_start,
+            _                                  => 0,
+        }
+    }
     pub fn completed_placement(&self) -> Range<usize> {
         assert!(self.is_completed());
         assert!(self.possible_placements.len() == 1);
         self.possible_placements[0].clone()
     }
-    pub fn to_colored_string(&self) -> ANSIString {
+    pub fn contains(&self, pos: usize) -> bool {
+        // whether this run is known to cover the square at the given position, i.e. it's completed and placed over it
+        self.completed && self.completed_placement().contains(&pos)
+    }
+    pub fn to_colored_string(&self) -> ANSIString<'static> {
         let style = match self.completed {
             true  => Style::new().fg(Colour::Fixed(241)),
             false => Style::default(),
@@ -256,7 +349,7 @@ impl Run {
 impl DirectionalSequence for Run {
     fn get_row_index(&self) -> usize { self.row_index }
     fn get_direction(&self) -> Direction { self.direction }
-    fn get_grid(&self)      -> &Rc<RefCell<Grid>> { &self.grid }
+    fn get_grid(&self)      -> &Arc<RwLock<Grid>> { &self.grid }
 }
 impl fmt::Display for Run {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -265,7 +358,7 @@ impl fmt::Display for Run {
 }
 
 impl CloneGridAware for Run {
-    fn clone_with_grid(&self, grid: &Rc<RefCell<Grid>>) -> Self {
+    fn clone_with_grid(&self, grid: &Arc<RwLock<Grid>>) -> Self {
         Run {
             direction:             self.direction.clone(),
             length:                self.length.clone(),
@@ -274,7 +367,7 @@ impl CloneGridAware for Run {
             row_length:            self.row_length.clone(),
             possible_placements:   self.possible_placements.clone(),
             completed:             self.completed.clone(),
-            grid:                  Rc::clone(grid),
+            grid:                  Arc::clone(grid),
         }
     }
 }
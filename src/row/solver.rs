@@ -1,20 +1,214 @@
 // vim: set ai et ts=4 sts=4 sw=4:
-use std::fmt;
 use std::ops::Range;
 use std::convert::{TryInto, TryFrom};
 use std::cmp::{min, max};
-use std::rc::{Rc, Weak};
-use std::cell::{Ref, RefMut, RefCell};
+use parking_lot::MappedRwLockWriteGuard;
 use std::collections::{HashSet, HashMap};
-use log::{trace, debug, info, log_enabled, Level::Trace};
+use log::{trace, log_enabled, Level::Trace};
 
-use super::{Row, Run, DirectionalSequence};
-use super::super::util::{Direction, Direction::{Horizontal, Vertical}, vec_remove_item};
-use super::super::grid::{Grid, Square, SquareStatus::{CrossedOut, FilledIn, Unknown},
+use super::{Row, Run, DirectionalSequence, LineStrategy, default_strategies};
+use super::super::util::vec_remove_item;
+use super::super::grid::{Square, SquareStatus, SquareStatus::{CrossedOut, FilledIn, Unknown},
                          Changes, Change, Error, HasGridLocation};
 
+pub fn solve_line(length: usize, clues: &[usize], cells: &[SquareStatus]) -> Result<Vec<SquareStatus>, Error>
+{
+    // solves a single line in isolation, given its run lengths and its currently known square statuses,
+    // by repeatedly running the row solvers on it until no more changes can be inferred.
+    // returns the statuses of all squares in the line as far as they could be deduced.
+    if cells.len() != length {
+        return Err(Error::Logic(format!(
+            "Line of length {} was given the state of {} squares", length, cells.len())));
+    }
+    // the runs need at least one square of space between them
+    let runs = clues.iter().filter(|&&len| len > 0).collect::<Vec<_>>();
+    if runs.iter().cloned().sum::<usize>() + runs.len().saturating_sub(1) > length {
+        return Err(Error::Logic(format!(
+            "Runs {:?} don't fit in a line of length {}", clues, length)));
+    }
+    if length == 0 {
+        return Ok(vec![]);
+    }
+
+    let mut row = Row::from_state(clues, cells)?;
+    let strategies = default_strategies();
+    while !row.is_completed() {
+        let changes = row.apply_strategies(&strategies)?;
+        if changes.is_empty() { break; }
+    }
+    Ok((0..length).map(|at| row.get_square(at).get_status())
+                  .collect())
+}
+
+pub fn solve_line_wrapped(length: usize, clues: &[usize], cells: &[SquareStatus]) -> Result<Vec<SquareStatus>, Error>
+{
+    // like solve_line, but for a line whose ends wrap around onto each other (as on a torus), so that a run
+    // can continue past the end of the line onto its start. runs are listed in the order of the square they
+    // start at, so it's always the last one that might wrap around.
+    //
+    // works by solving each way in which the line can be laid out as ordinary lines:
+    //  - nothing wraps: an ordinary line, except that its first and last runs can't touch across the edge,
+    //    so its first or its last square must be crossed out (unless a single run fills the whole line).
+    //  - the last run wraps, with some of its squares at the end of the line and the rest at its start:
+    //    the squares in between, minus the crossed out ones right next to the run, form an ordinary line
+    //    for the other runs.
+    // any square that has the same status in all of the layouts that can be solved is determined.
+    if cells.len() != length {
+        return Err(Error::Logic(format!(
+            "Line of length {} was given the state of {} squares", length, cells.len())));
+    }
+    let runs = clues.iter().cloned().filter(|&len| len > 0).collect::<Vec<_>>();
+    if runs.is_empty() {
+        return solve_line(length, clues, cells);
+    }
+    let mut layouts = Vec::<Vec<SquareStatus>>::new();
+
+    if runs == [length] {
+        layouts.extend(solve_line(length, &runs, cells).ok());
+    } else {
+        for edge in [0, length-1] {
+            if cells[edge] == FilledIn { continue; }
+            let mut edge_cells = cells.to_vec();
+            edge_cells[edge] = CrossedOut;
+            layouts.extend(solve_line(length, &runs, &edge_cells).ok());
+        }
+    }
+
+    // wrapping needs room for a gap after every run, including the last one
+    let (&last, others) = runs.split_last().unwrap();
+    if length >= runs.iter().sum::<usize>() + runs.len() {
+        for tail in 1..last {
+            let head = last - tail; // squares of the wrapping run at the start of the line
+            let middle = head+1 .. length-tail-1;
+            let mut layout = vec![Unknown; length];
+            for x in (0..head).chain(length-tail..length) {
+                layout[x] = FilledIn;
+            }
+            layout[head] = CrossedOut;
+            layout[length-tail-1] = CrossedOut;
+            if others.is_empty() {
+                for x in middle.clone() {
+                    layout[x] = CrossedOut;
+                }
+            }
+            if layout.iter().zip(cells).any(|(&ours, &known)| ours != Unknown && known != Unknown && ours != known) {
+                continue;
+            }
+            if !others.is_empty() {
+                match solve_line(middle.len(), others, &cells[middle.clone()]) {
+                    Ok(statuses) => layout[middle].copy_from_slice(&statuses),
+                    Err(_)       => continue,
+                }
+            }
+            layouts.push(layout);
+        }
+    }
+
+    let mut layouts = layouts.into_iter();
+    let first = layouts.next().ok_or_else(|| Error::Logic(format!(
+        "Runs {:?} can't be laid out in a wrapped line of length {} with the given squares", runs, length)))?;
+    Ok(layouts.fold(first, |determined, layout| determined.into_iter()
+                                                          .zip(layout)
+                                                          .map(|(a, b)| if a == b { a } else { Unknown })
+                                                          .collect()))
+}
+
 impl Row {
 
+    pub fn apply_strategies(&mut self, strategies: &[Box<dyn LineStrategy>]) -> Result<Changes, Error>
+    {
+        // runs the given line solving strategies on this row once, in order,
+        // and returns the list of changes they made.
+        let mut changes = Vec::<Change>::new();
+        for strategy in strategies {
+            changes.extend(strategy.apply(self)?);
+        }
+        Ok(changes)
+    }
+    pub fn apply_strategies_tallied(&mut self, strategies: &[Box<dyn LineStrategy>], tally: &mut HashMap<&'static str, usize>)
+        -> Result<Changes, Error>
+    {
+        // same as apply_strategies, but also adds the number of changes made by each strategy to the given tally,
+        // keyed by strategy name.
+        self.apply_strategies_explained(strategies, tally, &mut Vec::new())
+    }
+    pub fn apply_strategies_explained(&mut self, strategies: &[Box<dyn LineStrategy>],
+                                                 tally: &mut HashMap<&'static str, usize>,
+                                                 reasons: &mut Vec<&'static str>)
+        -> Result<Changes, Error>
+    {
+        // same as apply_strategies_tallied, but also appends the description of the strategy that made each change
+        // to the given list of reasons, in the same order as the returned changes.
+        let mut changes = Vec::<Change>::new();
+        for strategy in strategies {
+            let strategy_changes = strategy.apply(self)?;
+            *tally.entry(strategy.name()).or_insert(0) += strategy_changes.len();
+            reasons.resize(reasons.len() + strategy_changes.len(), strategy.describe());
+            changes.extend(strategy_changes);
+        }
+        Ok(changes)
+    }
+
+    pub fn enumerate_solutions(&self, max_solutions: usize) -> Result<Vec<Vec<SquareStatus>>, Error>
+    {
+        // generates every arrangement of the runs in this row that is consistent with the squares' known
+        // statuses and run assignments, as the full list of square statuses for each. fails once more than
+        // max_solutions arrangements are found, since their number grows combinatorially with the row's slack.
+        let runs = self.runs.iter().filter(|run| run.length > 0).collect::<Vec<_>>();
+        let statuses = (0..self.length).map(|pos| self.get_square(pos).get_status()).collect::<Vec<_>>();
+        let assigned = (0..self.length).map(|pos| self.get_square(pos).get_run_index(self.direction)).collect::<Vec<_>>();
+        // space needed by the runs from each index onwards, including the gaps between them
+        let mut min_lengths = vec![0; runs.len() + 1];
+        for i in (0..runs.len()).rev() {
+            min_lengths[i] = runs[i].length + if i + 1 < runs.len() { 1 + min_lengths[i+1] } else { 0 };
+        }
+
+        let mut solutions = Vec::<Vec<SquareStatus>>::new();
+        let mut current = vec![CrossedOut; self.length];
+        self._enumerate_from(0, 0, &runs, &min_lengths, &statuses, &assigned, &mut current, &mut solutions, max_solutions)?;
+        Ok(solutions)
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn _enumerate_from(&self, run_idx: usize, from: usize, runs: &[&Run], min_lengths: &[usize],
+                       statuses: &[SquareStatus], assigned: &[Option<usize>],
+                       current: &mut Vec<SquareStatus>, solutions: &mut Vec<Vec<SquareStatus>>, max_solutions: usize)
+        -> Result<(), Error>
+    {
+        // places runs[run_idx] at every possible start from the given position onwards (everything before that
+        // position has already been decided), and recurses into the next run; records a solution once all runs
+        // have been placed. squares in between runs are crossed out.
+        let can_be_crossed = |pos: usize| statuses[pos] != FilledIn && assigned[pos].is_none();
+        if run_idx == runs.len() {
+            if (from..self.length).all(can_be_crossed) {
+                if solutions.len() == max_solutions {
+                    return Err(Error::Logic(format!(
+                        "{} row {} has more than {} possible solutions", self.direction, self.index, max_solutions)));
+                }
+                solutions.push(current.clone());
+            }
+            return Ok(());
+        }
+        if from + min_lengths[run_idx] > self.length {
+            return Ok(()); // the remaining runs don't fit anymore
+        }
+        let run = runs[run_idx];
+        for start in from..=(self.length - min_lengths[run_idx]) {
+            if start > from && !can_be_crossed(start - 1) {
+                break; // the square we'd be skipping over has to be part of a run, and later runs can't reach back here
+            }
+            let end = start + run.length;
+            let fits = (start..end).all(|pos| statuses[pos] != CrossedOut && assigned[pos].is_none_or(|idx| idx == run.index))
+                       && (end == self.length || can_be_crossed(end));
+            if !fits { continue; }
+
+            current[start..end].iter_mut().for_each(|status| *status = FilledIn);
+            self._enumerate_from(run_idx + 1, (end + 1).min(self.length), runs, min_lengths, statuses, assigned,
+                                 current, solutions, max_solutions)?;
+            current[start..end].iter_mut().for_each(|status| *status = CrossedOut);
+        }
+        Ok(())
+    }
+
     pub fn update_possible_run_placements(&mut self) -> Result<(), Error>
     {
         // for each run in this row, calculates the possible placements of that run within the row,
@@ -46,8 +240,12 @@ impl Row {
             let len = run.length;
 
             if run.is_completed() {
-                // nothing to do
-                assert!(run.possible_placements.len() == 1);
+                // nothing to do, provided its placement is known
+                if run.possible_placements.len() != 1 {
+                    return Err(Error::Logic(format!(
+                        "Run #{} (len {}) of {} row {} is completed, but has {} possible placements instead of 1",
+                        run.index, run.length, self.direction, self.index, run.possible_placements.len())));
+                }
                 continue;
             }
             trace!("    evaluating run #{} (len {})", run_idx, len);
@@ -58,7 +256,10 @@ impl Row {
             let mut prev_run_earliest_end: isize = -1;
             if run_idx > 0 {
                 let prev_run = &self.runs[run_idx-1];
-                prev_run_earliest_end = prev_run.possible_placements[0].end.try_into().unwrap(); // [0] should always exist, was computed in one of the previous iterations
+                prev_run_earliest_end = match prev_run.possible_placements.first() {
+                    Some(range) => range.end.try_into().unwrap(),
+                    None        => return Err(self._no_placements_error(prev_run)),
+                };
             }
 
             let assigned_squares = (0..self.length).filter(|&pos| self.get_square(pos).has_run_assigned(run))
@@ -67,7 +268,7 @@ impl Row {
                                                  .collect::<Vec<_>>();
 
             let scan_start: usize = usize::try_from(prev_run_earliest_end + 1).unwrap();
-            let scan_end: usize = self.length - len + 1;
+            let scan_end: usize = (self.length + 1).saturating_sub(len); // no placements at all if the run is longer than the row
             trace!("      prev_run_earliest_end = {}, scan_start = {}, scan_end = {}", prev_run_earliest_end, scan_start, scan_end);
 
             #[allow(unused_parens)]
@@ -75,10 +276,7 @@ impl Row {
             {
                 let range = (s .. s+len);
                 let any_crossed_out      = range.clone().any(|pos| self.get_square(pos).get_status() == CrossedOut);
-                let any_belongs_to_other = range.clone().any(|pos| match self.get_square(pos).get_run_index(self.direction) {
-                                                                      Some(x) => x != run_idx,
-                                                                      None    => false,
-                                                                   });
+                let any_belongs_to_other = range.clone().any(|pos| self.run_at(pos).is_some_and(|run| run.index != run_idx));
                 let mut any_adj_sq_filled_in = false;
                 if range.start > 0 {
                     any_adj_sq_filled_in = any_adj_sq_filled_in || self.get_square(range.start-1).get_status() == FilledIn;
@@ -132,7 +330,7 @@ impl Row {
         // 2) R -> L scan
         trace!("");
         trace!("  update_possible_run_placements: R -> L scan");
-        for run_idx in (0..(self.runs.len()-1)).rev() {
+        for run_idx in (0..self.runs.len().saturating_sub(1)).rev() {
             let run = &self.runs[run_idx];
             trace!("    evaluating run #{} (len {})", run_idx, run.length);
 
@@ -141,13 +339,16 @@ impl Row {
             }
 
             let next_run = &self.runs[run_idx+1];
-            let next_run_latest_start: usize = next_run.possible_placements.last().unwrap().start.try_into().unwrap();
+            let next_run_latest_start: usize = match next_run.possible_placements.last() {
+                Some(range) => range.start,
+                None        => return Err(self._no_placements_error(next_run)),
+            };
             trace!("      next_run_latest_start (run #{}, {}) = {}", next_run.index, next_run.length, next_run_latest_start);
 
             // drop placements that don't respect the condition that this run's end position
             // must be no greater than the next one's latest start position - 1
             let run = &mut self.runs[run_idx];
-            run.possible_placements.retain(|range| range.end <= next_run_latest_start-1);
+            run.possible_placements.retain(|range| range.end < next_run_latest_start);
 
             if log_enabled!(Trace) {
                 trace!("      corrected ranges: {}", run.possible_placements.iter()
@@ -161,19 +362,257 @@ impl Row {
         // make sure all runs received at least one possible placement, otherwise something's wrong
         for run in &self.runs {
             if run.possible_placements.len() == 0 {
-                return Err(Error::Logic(format!(
-                    "Inconsistency: no possible placements found for {} run #{} of length {} in {} row {}",
-                    self.direction,
-                    run.index,
-                    run.length,
-                    self.direction,
-                    self.index
-                )));
+                return Err(self._no_placements_error(run));
             }
         }
         Ok(())
     }
 
+    fn _no_placements_error(&self, run: &Run) -> Error {
+        // the squares that can have eliminated the run's placements are the crossed out squares
+        // and the squares belonging to other runs within the part of the row that the run could
+        // occupy at all, given the lengths of the runs before and after it.
+        let span_start: usize = self.runs[..run.index].iter().map(|r| r.length + 1).sum();
+        let span_end: usize = self.length.saturating_sub(self.runs[run.index+1..].iter().map(|r| r.length + 1).sum());
+        let conflicting_squares = (span_start..span_end.max(span_start))
+            .filter(|&pos| {
+                self.get_square(pos).get_status() == CrossedOut || self.run_at(pos).is_some_and(|other| other.index != run.index)
+            })
+            .map(|pos| self.square_index(pos))
+            .collect::<Vec<_>>();
+
+        Error::Contradiction {
+            direction: self.direction,
+            index: self.index,
+            reason: format!("no possible placements found for run #{} of length {}", run.index, run.length),
+            conflicting_squares,
+        }
+    }
+
+    pub fn try_exact_fit(&mut self) -> Result<Changes, Error>
+    {
+        // fast path for saturated rows: if the runs plus the mandatory gaps between them take up the entire
+        // row, then each run can only be in one place, and the whole row is determined right away.
+        //
+        // e.g. a row of length 8 with runs of length 3, 1 and 2:
+        //
+        //      0 1 2 3 4 5 6 7             0 1 2 3 4 5 6 7
+        //     [. . . . . . . .]    ->     [# # # x # x # #]
+        //
+        let mut changes = Vec::<Change>::new();
+        if self.completed || self.is_trivially_empty() || self.min_length() != self.length {
+            return Ok(changes);
+        }
+        trace!("  try_exact_fit:");
+        let mut start = 0;
+        for run_idx in 0..self.runs.len() {
+            let len = self.runs[run_idx].length;
+            if len == 0 { continue; }
+            changes.extend(self._clamp_run_at(run_idx, start)?);
+            start += len + 1;
+        }
+        Ok(changes)
+    }
+
+    pub fn clamp_edges(&mut self) -> Result<Changes, Error>
+    {
+        // if the first square in the row that isn't crossed out is filled in, then it must be the start
+        // of the first run, so that run's position is fixed; likewise for the last run at the other end.
+        //
+        // e.g. a row whose first run has length 3 and whose first square is filled in:
+        //
+        //      0 1 2 3 4 5 6 7             0 1 2 3 4 5 6 7
+        //     [# . . . . . . .]    ->     [# # # x . . . .]
+        //
+        trace!("  clamp_edges:");
+        let mut changes = Vec::<Change>::new();
+        let first_open = (0..self.length).find(|&pos| self.get_square(pos).get_status() != CrossedOut);
+        let last_open = (0..self.length).rev().find(|&pos| self.get_square(pos).get_status() != CrossedOut);
+        let (first_open, last_open) = match (first_open, last_open) {
+            (Some(first), Some(last)) => (first, last),
+            _                         => return Ok(changes), // everything's crossed out
+        };
+        let first_run = self.runs.iter().position(|run| run.length > 0);
+        let last_run = self.runs.iter().rposition(|run| run.length > 0);
+        let (first_run, last_run) = match (first_run, last_run) {
+            (Some(first), Some(last)) => (first, last),
+            _                         => return Ok(changes), // no runs to place
+        };
+
+        if self.get_square(first_open).get_status() == FilledIn {
+            changes.extend(self._clamp_run_at(first_run, first_open)?);
+        }
+        if self.get_square(last_open).get_status() == FilledIn {
+            let len = self.runs[last_run].length;
+            if last_open + 1 < len {
+                return Err(self._no_placements_error(&self.runs[last_run]));
+            }
+            changes.extend(self._clamp_run_at(last_run, last_open + 1 - len)?);
+        }
+        Ok(changes)
+    }
+
+    fn _clamp_run_at(&mut self, run_idx: usize, start: usize) -> Result<Changes, Error>
+    {
+        // fixes the given run at the given starting position: fills in and assigns its squares,
+        // and marks it completed (which crosses out the squares on either side of it).
+        let mut changes = Vec::<Change>::new();
+        if self.runs[run_idx].is_completed() {
+            return Ok(changes);
+        }
+        if start + self.runs[run_idx].length > self.length {
+            return Err(self._no_placements_error(&self.runs[run_idx]));
+        }
+        let run = &mut self.runs[run_idx];
+        trace!("    run #{} (len {}) is clamped to the edge at [{},{}]", run.index, run.length, start, start + run.length - 1);
+        for pos in start..start + run.length {
+            let mut square: MappedRwLockWriteGuard<Square> = run.get_square_mut(pos);
+            if let Some(change) = square.set_status(FilledIn)? {
+                changes.push(Change::from(change));
+            }
+            if let Some(change) = square.assign_run(run)? {
+                changes.push(Change::from(change));
+            }
+        }
+        changes.extend(run.complete(start)?);
+        Ok(changes)
+    }
+
+    pub fn extend_from_walls(&mut self) -> Result<Changes, Error>
+    {
+        // a filled in square right next to a wall (a crossed out square or the edge of the row) must be the
+        // start (or end) of whichever run it belongs to. all runs that could contain it are at least as long as
+        // the shortest of them, so at least that many squares away from the wall must be filled in as well.
+        // if they're all of that same length, the run ends there, and the square after it is crossed out.
+        //
+        // e.g. a row with runs of length 3 and 3, where the square at 3 is filled in next to a crossed out one:
+        //
+        //      0 1 2 3 4 5 6 7 8 9             0 1 2 3 4 5 6 7 8 9
+        //     [. . x # . . . . . .]    ->     [. . x # # # x . . .]
+        //
+        trace!("  extend_from_walls:");
+        let mut changes = Vec::<Change>::new();
+        for pos in 0..self.length
+        {
+            if self.get_square(pos).get_status() != FilledIn {
+                continue;
+            }
+            let wall_before = pos == 0 || self.get_square(pos-1).get_status() == CrossedOut;
+            let wall_after = pos + 1 == self.length || self.get_square(pos+1).get_status() == CrossedOut;
+            if wall_before == wall_after {
+                continue; // either not against a wall, or nowhere to extend to
+            }
+            let lengths = self.possible_runs_for_square(pos).iter().map(|&r| self.runs[r].length).collect::<Vec<_>>();
+            let (min_length, max_length) = match (lengths.iter().min(), lengths.iter().max()) {
+                (Some(&min), Some(&max)) => (min, max),
+                _                        => continue, // no run fits here; that's for infer_run_assignments to report
+            };
+            let range = match wall_before {
+                true  => pos .. pos + min_length,
+                false => (pos + 1).saturating_sub(min_length) .. pos + 1,
+            };
+            if range.len() < min_length || range.end > self.length {
+                return Err(Error::Logic(format!(
+                    "Inconsistency: filled in square at {} in {} row {} is against a wall, but there is no room for a run of length {} away from it",
+                    pos, self.direction, self.index, min_length)));
+            }
+            trace!("    square at {} is against a wall; runs covering it have length >= {}, filling in [{},{}]", pos, min_length, range.start, range.end-1);
+            // the square on the other end of the run, if it's known to end there
+            let beyond = match wall_before {
+                true  => Some(range.end).filter(|&x| x < self.length),
+                false => range.start.checked_sub(1),
+            }.filter(|_| max_length == min_length);
+            for x in range {
+                if let Some(change) = self.get_square_mut(x).set_status(FilledIn)? {
+                    changes.push(Change::from(change));
+                }
+            }
+            if let Some(x) = beyond {
+                trace!("    runs covering it all have length {}, crossing out {}", min_length, x);
+                if let Some(change) = self.get_square_mut(x).set_status(CrossedOut)? {
+                    changes.push(Change::from(change));
+                }
+            }
+        }
+        Ok(changes)
+    }
+
+    pub fn pack_tight_fields(&mut self) -> Result<Changes, Error>
+    {
+        // if the runs that can be placed within a field (a stretch of squares that aren't crossed out) all
+        // have to be placed within it, and they exactly fill it when packed together with single gaps in
+        // between, then every square in the field is determined.
+        //
+        // e.g. a field [2,7] that must hold runs of length 2 and 3, and can't hold any other runs:
+        //
+        //      0 1 2 3 4 5 6 7 8             0 1 2 3 4 5 6 7 8
+        //     [. x . . . . . . x]    ->     [. x # # x # # # x]
+        //
+        trace!("  pack_tight_fields:");
+        let mut changes = Vec::<Change>::new();
+        for field in self.get_fields() {
+            let within = |placement: &Range<usize>| placement.start >= field.start && placement.end <= field.end;
+            let runs_in_field = self.runs.iter()
+                                         .filter(|run| run.length > 0 && run.possible_placements.iter().any(within))
+                                         .collect::<Vec<_>>();
+            if runs_in_field.is_empty() { continue; }
+            if !runs_in_field.iter().all(|run| run.possible_placements.iter().all(within)) {
+                continue; // some runs might end up elsewhere, so we don't know which ones the field holds
+            }
+            let packed_length = runs_in_field.iter().map(|run| run.length).sum::<usize>() + runs_in_field.len() - 1;
+            if packed_length != field.len() { continue; }
+
+            trace!("    runs {} exactly fill field [{},{}]",
+                runs_in_field.iter().map(|run| format!("#{}", run.index)).collect::<Vec<_>>().join(", "),
+                field.start, field.end-1);
+            let run_indices = runs_in_field.iter().map(|run| run.index).collect::<Vec<_>>();
+            let mut start = field.start;
+            for run_idx in run_indices {
+                changes.extend(self._clamp_run_at(run_idx, start)?);
+                start += self.runs[run_idx].length + 1;
+            }
+        }
+        Ok(changes)
+    }
+
+    pub fn fill_overlap(&mut self) -> Result<Changes, Error>
+    {
+        // fast path for the most common deduction: the squares between a run's rightmost possible start
+        // and its leftmost possible end are covered by every possible placement of that run in between,
+        // so they must be filled in and belong to that run.
+        //
+        // e.g. a run of length 4 whose leftmost placement is [1,4] and rightmost placement is [3,6]:
+        //
+        //      0 1 2 3 4 5 6 7
+        //     [. . . # # . . .]
+        //
+        trace!("  fill_overlap:");
+        let mut changes = Vec::<Change>::new();
+        for run in &self.runs
+        {
+            if run.is_completed() { continue; } // nothing to do
+            let (min_start, max_start) = match (run.min_start(), run.max_start()) {
+                (Some(min_start), Some(max_start)) => (min_start, max_start),
+                _                                  => continue,
+            };
+            let min_end = min_start + run.length;
+            if max_start >= min_end { continue; } // no overlap
+
+            trace!("    run #{} (len {}) overlaps between leftmost and rightmost placements at [{},{}]",
+                run.index, run.length, max_start, min_end-1);
+            for pos in max_start..min_end {
+                let mut square: MappedRwLockWriteGuard<Square> = run.get_square_mut(pos);
+                if let Some(change) = square.set_status(FilledIn)? {
+                    changes.push(Change::from(change));
+                }
+                if let Some(change) = square.assign_run(run)? {
+                    changes.push(Change::from(change));
+                }
+            }
+        }
+        Ok(changes)
+    }
+
     pub fn infer_status_assignments(&mut self) -> Result<Changes, Error>
     {
         trace!("  infer_status_assignments:");
@@ -186,9 +625,10 @@ impl Row {
         {
             if run.is_completed() { continue; } // nothing to do
             for pos in 0..self.length {
-                let mut square: RefMut<Square> = run.get_square_mut(pos);
                 if run.possible_placements.iter().all(|range| range.contains(&pos))
                 {
+                    // keep the square borrowed only for as long as we're modifying it
+                    let mut square: MappedRwLockWriteGuard<Square> = run.get_square_mut(pos);
                     trace!("    square {} is present in all possible placements of run #{} (len {}), marking it filled and assigned",
                         square.fmt_location(), run.index, run.length);
                     if let Some(change) = square.set_status(FilledIn)? {
@@ -333,16 +773,14 @@ impl Row {
             }
 
             // for each sequence of filled squares, determine the set of possible runs that could
-            // be assigned to it according to the current information.
-            // TODO: hashmap isn't necessary here, could also just store this as a Vec<Vec<usize>>
-            let mut possible_runs_map = HashMap::<usize, Vec<usize>>::new(); // maps sequence index to list of possible run indices
-            for (i, seq) in filled_sequences.iter().enumerate() {
-                let possible_runs = self.possible_runs_for_sequence(seq);
-                possible_runs_map.insert(i, possible_runs);
-            }
+            // be assigned to it according to the current information. this is indexed by sequence, so that
+            // the sequences are processed (and their changes reported) in left-to-right order.
+            let mut possible_runs_per_seq = filled_sequences.iter()
+                                                            .map(|seq| self.possible_runs_for_sequence(seq))
+                                                            .collect::<Vec<Vec<usize>>>();
 
             trace!("    list of possible runs per sequence:");
-            for (&seq_idx, possible_runs) in &possible_runs_map
+            for (seq_idx, possible_runs) in possible_runs_per_seq.iter().enumerate()
             {
                 let seq = &filled_sequences[seq_idx];
                 trace!("      seq [{:-2}, {:-2}]: possible runs = {}",
@@ -364,14 +802,14 @@ impl Row {
             for (i, seq) in filled_sequences.iter().enumerate() {
                 if (leftmost_seq.start .. seq.end).len() > leftmost_run.length {
                     // this sequence is further than length(leftmost_run) away from the leftmost sequence; can't have the leftmost run as a possibility
-                    let removed = vec_remove_item(&mut possible_runs_map.get_mut(&i).unwrap(), &leftmost_run.index);
+                    let removed = vec_remove_item(&mut possible_runs_per_seq[i], &leftmost_run.index);
                     if let Some(_) = removed {
                         trace!("    removed the possibility of leftmost run #{} (len {}) being assigned to the sequence at [{},{}]: is more than the length of the leftmost run {} removed from the leftmost sequence at [{},{}]",
                             leftmost_run.index, leftmost_run.length, seq.start, seq.end-1, leftmost_run.length, leftmost_seq.start, leftmost_seq.end-1);
                     }
                 }
                 if (seq.start .. rightmost_seq.end).len() > rightmost_run.length {
-                    let removed = vec_remove_item(&mut possible_runs_map.get_mut(&i).unwrap(), &rightmost_run.index);
+                    let removed = vec_remove_item(&mut possible_runs_per_seq[i], &rightmost_run.index);
                     if let Some(_) = removed {
                         trace!("    removed the possibility of rightmost run #{} (len {}) being assigned to the sequence at [{},{}]: is more than the length of the rightmost run {} removed from the rightmost sequence at [{},{}]",
                             rightmost_run.index, rightmost_run.length, seq.start, seq.end-1, rightmost_run.length, rightmost_seq.start, rightmost_seq.end-1);
@@ -384,15 +822,13 @@ impl Row {
 
             // -> for each run, see if there are any sequences on which either ONLY previous runs or ONLY following runs are possible.
             for run_idx in runs_range.start..runs_range.end {
-                let rightmost_seq_wonly_prev_runs = (0..filled_sequences.len()).filter(|&seq_idx| possible_runs_map[&seq_idx].iter().all(|&possible_run_idx| possible_run_idx < run_idx))
-                                                                               .last();
-                let leftmost_seq_wonly_next_runs = (0..filled_sequences.len()).filter(|seq_idx| possible_runs_map[&seq_idx].iter().all(|&possible_run_idx| possible_run_idx > run_idx))
-                                                                              .next();
+                let rightmost_seq_wonly_prev_runs = (0..filled_sequences.len()).rev().find(|&seq_idx| possible_runs_per_seq[seq_idx].iter().all(|&possible_run_idx| possible_run_idx < run_idx));
+                let leftmost_seq_wonly_next_runs = (0..filled_sequences.len()).find(|&seq_idx| possible_runs_per_seq[seq_idx].iter().all(|&possible_run_idx| possible_run_idx > run_idx));
 
                 // drop this run from the sequences where they can't appear
                 if let Some(rightmost_idx) = rightmost_seq_wonly_prev_runs {
                     for seq_idx in 0..rightmost_idx {
-                        let removed = vec_remove_item(&mut possible_runs_map.get_mut(&seq_idx).unwrap(), &run_idx);
+                        let removed = vec_remove_item(&mut possible_runs_per_seq[seq_idx], &run_idx);
                         if let Some(_) = removed {
                             let run = &self.runs[run_idx];
                             let seq = &filled_sequences[seq_idx];
@@ -404,7 +840,7 @@ impl Row {
                 }
                 if let Some(leftmost_idx) = leftmost_seq_wonly_next_runs {
                     for seq_idx in leftmost_idx+1..filled_sequences.len() {
-                        let removed = vec_remove_item(&mut possible_runs_map.get_mut(&seq_idx).unwrap(), &run_idx);
+                        let removed = vec_remove_item(&mut possible_runs_per_seq[seq_idx], &run_idx);
                         if let Some(_) = removed {
                             let run = &self.runs[run_idx];
                             let seq = &filled_sequences[seq_idx];
@@ -424,7 +860,7 @@ impl Row {
             //      * if all possible runs for a sequence are of a certain minimal length, we can 'bounce' that length against the edges
             //          of its containing field to discover additional filled squares.
 
-            for (&seq_idx, possible_runs) in &possible_runs_map
+            for (seq_idx, possible_runs) in possible_runs_per_seq.iter().enumerate()
             {
                 let seq = &filled_sequences[seq_idx];
                 if possible_runs.len() == 0 {
@@ -518,8 +954,8 @@ impl Row {
         {
             let mut unique_runs = HashSet::<usize>::new();
             for i in seq.start..seq.end {
-                if let Some(x) = self.get_square(i).get_run_index(self.direction) {
-                    unique_runs.insert(x);
+                if let Some(run) = self.run_at(i) {
+                    unique_runs.insert(run.index);
                 }
             }
 
@@ -552,6 +988,74 @@ impl Row {
         Ok(changes)
     }
 
+    pub fn cross_forced_gaps(&mut self) -> Result<Changes, Error>
+    {
+        // the squares between two consecutive completed runs (or between the edge of the row and the first or
+        // last completed run) can only be taken by the runs that come in between them in the clue order.
+        // any stretch of squares in there that isn't crossed out but is too short to hold the shortest of
+        // those runs must be crossed out entirely; if there are no runs in between at all, the whole gap is.
+        //
+        // e.g. runs of length 1, 2 and 1, where both runs of length 1 are completed:
+        //
+        //      0 1 2 3 4 5 6 7 8 9             0 1 2 3 4 5 6 7 8 9
+        //     [# x . x . . . . x #]    ->     [# x x x . . . . x #]
+        //
+        trace!("  cross_forced_gaps:");
+        let mut changes = Vec::<Change>::new();
+        if self.is_completed() || self.is_trivially_empty() {
+            return Ok(changes);
+        }
+
+        // each gap is given by the range of squares it spans and the range of (indices of) runs that must go in it
+        let mut gaps = Vec::<(Range<usize>, Range<usize>)>::new();
+        let (mut gap_start, mut first_run) = (0, 0);
+        for run in self.runs.iter().filter(|run| run.is_completed()) {
+            let placement = run.completed_placement();
+            gaps.push((gap_start..placement.start, first_run..run.index));
+            gap_start = placement.end;
+            first_run = run.index + 1;
+        }
+        if first_run == 0 {
+            return Ok(changes); // no completed runs, so the whole row is one gap that may hold any run
+        }
+        gaps.push((gap_start..self.length, first_run..self.runs.len()));
+
+        for (squares, run_indices) in gaps {
+            if squares.is_empty() { continue; }
+            let min_length = self.runs[run_indices.clone()].iter().map(|run| run.length).min().unwrap_or(0);
+            let fields = self._ranges_of_squares(|sq, x| squares.contains(&x) && sq.get_status() != CrossedOut);
+            for field in fields.into_iter().filter(|field| run_indices.is_empty() || field.len() < min_length) {
+                trace!("    field [{},{}] between completed runs can't hold any of runs {:?}", field.start, field.end-1, run_indices);
+                for x in field {
+                    if let Some(change) = self.get_square_mut(x).set_status(CrossedOut)? {
+                        changes.push(Change::from(change));
+                    }
+                }
+            }
+        }
+        Ok(changes)
+    }
+
+    pub fn solve_wrapped(&mut self) -> Result<Changes, Error>
+    {
+        // solves this row as one whose ends wrap around onto each other; see solve_line_wrapped.
+        // runs aren't tracked across the edge, so no run assignments are made, and the row counts
+        // as completed once all of its squares are known.
+        let clues = self.runs.iter().map(|run| run.length).collect::<Vec<_>>();
+        let cells = (0..self.length).map(|at| self.get_square(at).get_status()).collect::<Vec<_>>();
+        let mut changes = Vec::<Change>::new();
+        for (at, status) in solve_line_wrapped(self.length, &clues, &cells)?.into_iter().enumerate() {
+            if status == Unknown { continue; }
+            if let Some(change) = self.get_square_mut(at).set_status(status)? {
+                changes.push(Change::from(change));
+            }
+        }
+        if (0..self.length).all(|at| self.get_square(at).get_status() != Unknown) {
+            self.completed = true;
+        }
+        Ok(changes)
+    }
+
     pub fn check_completed(&mut self) -> Result<Changes, Error> {
         // if all runs in this row have been completed, clear out any remaining squares
         // (also handles cases where the row is empty or only has 0-length runs)
@@ -561,7 +1065,7 @@ impl Row {
         if is_trivially_empty || self.runs.iter().all(|r| r.is_completed())
         {
             for x in 0..self.length {
-                let mut square: RefMut<Square> = self.get_square_mut(x);
+                let mut square: MappedRwLockWriteGuard<Square> = self.get_square_mut(x);
                 // if this row is empty, cross out everything; otherwise, only cross out whatever wasn't already crossed out
                 if is_trivially_empty || square.get_status() != FilledIn {
                     if let Some(change) = square.set_status(CrossedOut)? {
@@ -584,3 +1088,141 @@ impl Row {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(art: &str) -> Vec<SquareStatus> {
+        // square statuses from one character per square: '#' filled in, 'x' crossed out, '.' unknown
+        art.chars().map(|c| match c {
+            '#' => FilledIn,
+            'x' => CrossedOut,
+            _   => Unknown,
+        }).collect()
+    }
+
+    fn row_state(row: &Row) -> Vec<SquareStatus> {
+        (0..row.length).map(|at| row.get_square(at).get_status()).collect()
+    }
+
+    #[test]
+    fn fill_overlap_reaches_the_right_edge() {
+        // runs that fit exactly, with the last one right against the edge
+        let mut row = Row::from_state(&[2, 3], &line("......")).unwrap();
+        row.fill_overlap().unwrap();
+        assert_eq!(row_state(&row), line("##.###"));
+        assert_eq!(solve_line(6, &[2, 3], &line("......")).unwrap(), line("##x###"));
+    }
+
+    #[test]
+    fn overlap_strategies_fill_overlap_first() {
+        // with a long run in a wide row, FillOverlap makes the deduction before any of the infer_* passes get to it
+        let mut row = Row::from_state(&[8], &line("..........")).unwrap();
+        let mut tally = HashMap::new();
+        row.apply_strategies_tallied(&super::super::overlap_strategies(), &mut tally).unwrap();
+        assert_eq!(row_state(&row), line("..######.."));
+        assert_eq!(tally.get("fill_overlap"), Some(&12)); // six squares filled in, and each assigned to the run
+        assert_eq!(tally.get("infer_status_assignments").copied().unwrap_or(0), 0);
+    }
+
+    #[test]
+    fn extend_from_walls_with_known_run_length() {
+        // the example from the comments: only runs of length 3 can cover the square at 3, so the run ends at 5
+        let mut row = Row::from_state(&[3, 3], &line("..x#......")).unwrap();
+        row.extend_from_walls().unwrap();
+        assert_eq!(row_state(&row), line("..x###x..."));
+
+        // same thing mirrored, against the right edge of the row
+        let mut row = Row::from_state(&[3], &line(".........#")).unwrap();
+        row.extend_from_walls().unwrap();
+        assert_eq!(row_state(&row), line("......x###"));
+
+        // runs of different lengths could cover it, so only the shortest one is certain
+        let mut row = Row::from_state(&[2, 4], &line("...x#.......")).unwrap();
+        row.extend_from_walls().unwrap();
+        assert_eq!(row_state(&row), line("...x##......"));
+    }
+
+    #[test]
+    fn cross_forced_gaps_crosses_out_too_small_gap() {
+        // the example from the comments: the square at 2 is too small for the run of length 2
+        let mut row = Row::from_state(&[1, 2, 1], &line("#x.x....x#")).unwrap();
+        row.runs[0].complete(0).unwrap();
+        row.runs[2].complete(9).unwrap();
+        row.cross_forced_gaps().unwrap();
+        assert_eq!(row_state(&row), line("#xxx....x#"));
+    }
+
+    #[test]
+    fn cross_forced_gaps_leaves_exactly_fitting_gap() {
+        // the gap between the completed runs is just wide enough for the run of length 2, so it stays open
+        let mut row = Row::from_state(&[1, 2, 1], &line("#x..x#....")).unwrap();
+        row.runs[0].complete(0).unwrap();
+        row.runs[2].complete(5).unwrap();
+        row.cross_forced_gaps().unwrap();
+        assert_eq!(row_state(&row), line("#x..x#xxxx"));
+    }
+
+    #[test]
+    fn fill_overlap_crosses_out_trailing_separator() {
+        // a run that ends one square short of the edge leaves that last square crossed out
+        let mut row = Row::from_state(&[3], &line("x#...")).unwrap();
+        row.fill_overlap().unwrap();
+        assert_eq!(row_state(&row), line("x###."));
+        assert_eq!(solve_line(5, &[3], &line("x#...")).unwrap(), line("x###x"));
+        assert_eq!(solve_line(5, &[3], &line("...#x")).unwrap(), line("x###x"));
+    }
+
+    #[test]
+    fn possible_runs_for_sequence_edge_cases() {
+        let row = Row::from_state(&[2, 1], &line(".......")).unwrap();
+        assert_eq!(row.possible_runs_for_sequence(&(1..4)), Vec::<usize>::new()); // longer than every run
+        assert_eq!(row.possible_runs_for_sequence(&(2..2)), Vec::<usize>::new()); // empty
+
+        // at either edge of the row, only the first and last runs fit, respectively
+        let row = Row::from_state(&[2, 3], &line("........")).unwrap();
+        assert_eq!(row.possible_runs_for_sequence(&(0..1)), vec![0]);
+        assert_eq!(row.possible_runs_for_sequence(&(7..8)), vec![1]);
+        assert_eq!(row.possible_runs_for_sequence(&(5..8)), vec![1]);
+
+        // runs of identical lengths can both hold a sequence in the middle, but not one near the edges
+        let row = Row::from_state(&[2, 2], &line("........")).unwrap();
+        assert_eq!(row.possible_runs_for_sequence(&(3..5)), vec![0, 1]);
+        assert_eq!(row.possible_runs_for_sequence(&(1..3)), vec![0]);
+        assert_eq!(row.possible_runs_for_sequence(&(5..7)), vec![1]);
+    }
+
+    #[test]
+    fn solve_line_without_runs() {
+        assert_eq!(solve_line(3, &[], &line("...")).unwrap(), line("xxx"));
+        assert_eq!(solve_line(3, &[0], &line("...")).unwrap(), line("xxx"));
+        assert_eq!(solve_line(0, &[], &line("")).unwrap(), line(""));
+        assert!(solve_line(3, &[], &line(".#.")).is_err());
+        let row = Row::from_state(&[], &line("...")).unwrap();
+        assert_eq!(row.possible_runs_for_square(1), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn clamp_edges_pins_runs_to_filled_ends() {
+        let clamp = |clues: &[usize], art: &str| {
+            let mut row = Row::from_state(clues, &line(art)).unwrap();
+            row.clamp_edges().unwrap();
+            row_state(&row)
+        };
+        assert_eq!(clamp(&[3, 1], "#......."), line("###x...."));
+        assert_eq!(clamp(&[1, 2], ".......#"), line(".....x##"));
+        assert_eq!(clamp(&[2, 2], "#......#"), line("##x..x##"));
+        assert_eq!(clamp(&[3], "x#......"), line("x###x..."));   // the first open square counts as the edge
+        assert_eq!(clamp(&[2, 1], "..#....."), line("..#....."));  // not at the edge, so nothing to clamp
+    }
+
+    #[test]
+    fn solve_line_rejects_runs_that_dont_fit() {
+        assert!(matches!(solve_line(3, &[2, 2], &line("...")), Err(Error::Logic(_))));
+        assert!(matches!(solve_line(3, &[4], &line("...")), Err(Error::Logic(_))));
+        assert!(matches!(solve_line(0, &[1], &line("")), Err(Error::Logic(_))));
+        assert!(matches!(solve_line(3, &[1], &line("..")), Err(Error::Logic(_))));
+        assert_eq!(solve_line(5, &[2, 2], &line(".....")).unwrap(), line("##x##"));
+    }
+}
@@ -0,0 +1,77 @@
+// vim: set ai et ts=4 sts=4 sw=4:
+use super::puzzle::{Puzzle, Solver};
+use super::grid::SquareStatus;
+
+/// Helpers for checking hand-made puzzles from `#[test]` functions.
+///
+/// `assert_uniquely_solvable` builds a puzzle from its clues, solves it and panics unless it has exactly one
+/// solution. Solutions are counted by `count_solutions`, which runs the line solvers and, whenever they get
+/// stuck, tries both statuses for the first unknown square; it stops as soon as the requested number of
+/// solutions has been found, so checking for uniqueness never has to enumerate more than two of them.
+pub fn assert_uniquely_solvable(row_clues: Vec<Vec<usize>>, col_clues: Vec<Vec<usize>>) {
+    let puzzle = Puzzle::from_clues(row_clues, col_clues).unwrap_or_else(|e| panic!("Invalid puzzle: {}", e));
+    match count_solutions(&puzzle, 2) {
+        0 => panic!("Puzzle has no solution"),
+        1 => {},
+        _ => panic!("Puzzle has more than one solution"),
+    }
+}
+
+pub fn count_solutions(puzzle: &Puzzle, max_solutions: usize) -> usize {
+    // returns the number of solutions of the given puzzle, up to max_solutions.
+    // any error from the line solvers means the current state has no solutions at all.
+    if max_solutions == 0 {
+        return 0;
+    }
+    let mut solver = Solver::new(puzzle.clone());
+    if solver.any(|iteration_result| iteration_result.is_err()) {
+        return 0;
+    }
+    if solver.puzzle.is_completed() {
+        return 1;
+    }
+
+    let (x, y) = solver.puzzle.unknown_squares()[0]; // has to exist, otherwise the puzzle would be completed
+    let mut count = 0;
+    for status in [SquareStatus::FilledIn, SquareStatus::CrossedOut] {
+        let edited_puzzle = solver.puzzle.clone();
+        if edited_puzzle.get_square_mut(x, y).set_status(status).is_err() {
+            continue;
+        }
+        count += count_solutions(&edited_puzzle, max_solutions - count);
+        if count >= max_solutions {
+            break;
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unique_puzzle_passes() {
+        // solution:
+        //   ##.
+        //   .#.
+        //   ###
+        assert_uniquely_solvable(vec![vec![2], vec![1], vec![3]],
+                                 vec![vec![1, 1], vec![3], vec![1]]);
+    }
+
+    #[test]
+    #[should_panic(expected = "more than one solution")]
+    fn ambiguous_puzzle_panics() {
+        // both diagonals of a 2x2 grid fit these clues
+        assert_uniquely_solvable(vec![vec![1], vec![1]], vec![vec![1], vec![1]]);
+    }
+
+    #[test]
+    fn count_solutions_stops_at_max() {
+        let puzzle = Puzzle::from_clues(vec![vec![1], vec![1]], vec![vec![1], vec![1]]).unwrap();
+        assert_eq!(count_solutions(&puzzle, 5), 2);
+        assert_eq!(count_solutions(&puzzle, 1), 1);
+        assert_eq!(count_solutions(&puzzle, 0), 0);
+    }
+}
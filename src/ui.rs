@@ -1,58 +1,284 @@
 // vim: set ai et ts=4 sts=4 sw=4:
-use super::puzzle::{Puzzle, Solver};
-use super::grid::SquareStatus;
-use super::row::{Row, DirectionalSequence};
-use super::util::{Direction::*};
-use super::Args;
+use super::puzzle::{Puzzle, Solver, SpeculativeStep};
+use super::grid::{SquareStatus, Change, Changes, Error, HasGridLocation};
+use super::row::Row;
+use super::util::{Direction, Direction::*};
+use super::options::RenderOptions;
 
-use std::convert::TryFrom;
+use std::collections::VecDeque;
 use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use log::warn;
 use piston::window::WindowSettings;
-use piston::event_loop::{Events, EventLoop, EventSettings};
+use piston::event_loop::{Events, EventSettings};
 use piston::input::{RenderEvent, GenericEvent, Button, Key};
 use glutin_window::GlutinWindow;
 use graphics::{Context, Graphics, clear};
-use graphics::{Rectangle, Line, Transformed, Image, Text};
-use graphics::types::Color;
+use graphics::{Rectangle, Line, Transformed, Text};
+use graphics::types::{Color, FontSize};
 use graphics::character::CharacterCache;
 use opengl_graphics::{OpenGL, GlGraphics, Filter, GlyphCache, TextureSettings};
 
+const PAN_STEP: f64 = 40.0;  // distance (in pixels) to move the puzzle by per arrow key press
+const ZOOM_STEP: f64 = 1.25; // factor to zoom in or out by per +/- key press
+const BUILTIN_FONT: &[u8] = include_bytes!("../FiraSans-Regular.ttf"); // used when no (loadable) --font is given
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum StepMode {
+    Iteration,    // each step runs a full solver iteration
+    SingleChange, // each step reveals a single change of the current solver iteration
+}
+impl fmt::Display for StepMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            StepMode::Iteration    => "iteration",
+            StepMode::SingleChange => "single change",
+        })
+    }
+}
+
 struct PuzzleController {
     //pub puzzle: Puzzle,
     pub solver: Solver,
     pub cursor_pos: [f64;2],
+    pub window_size: [f64;2],
+    pub zoom: f64, // scale factor on top of the square size that fits the whole puzzle in the window
+    pub pan: [f64;2], // offset of the drawn puzzle from its default position
+    pub step_mode: StepMode,
+    pub pending_changes: VecDeque<Change>, // changes of the last solver iteration that have yet to be shown
+    pub shown_puzzle: Option<Puzzle>,      // puzzle state being shown while pending changes are doled out
+    pub last_changes: Changes,             // changes made by the most recent step, to be highlighted
+    pub play: bool,                        // whether to automatically step the solver on a timer
+    pub play_elapsed: f64,                 // time (in seconds) since the last automatic step
+    pub play_stop_reason: Option<String>,  // why auto-play last stopped by itself, if it did
+    pub speculate: bool,                   // whether to guess (and backtrack) once logic runs out, rather than stop
+    pub ctrl_down: bool,                   // whether a Ctrl key is currently held down
+    pub state_path: PathBuf,               // file to save the grid state to on Ctrl+S
+    pub message: Option<String>,           // outcome of the last manual action, shown in the info panel
+    pub initial_puzzle: Puzzle,            // puzzle as it was handed to the controller, to start over from
 }
 impl PuzzleController {
     pub fn new(puzzle: Puzzle) -> Self {
+        let initial_puzzle = puzzle.clone();
+        let mut solver = Solver::new(puzzle);
+        solver.strict = true; // squares can be marked by hand, catch mistakes as they're made
         PuzzleController {
-            solver: Solver::new(puzzle),
-            cursor_pos: [-1.0,-1.0]
+            initial_puzzle,
+            solver,
+            cursor_pos: [-1.0,-1.0],
+            window_size: [0.0,0.0],
+            zoom: 1.0,
+            pan: [0.0,0.0],
+            step_mode: StepMode::Iteration,
+            pending_changes: VecDeque::new(),
+            shown_puzzle: None,
+            last_changes: Vec::new(),
+            play: false,
+            play_elapsed: 0.0,
+            play_stop_reason: None,
+            speculate: false,
+            ctrl_down: false,
+            state_path: PathBuf::from("puzzle.state"),
+            message: None,
+        }
+    }
+    pub fn reset(&mut self) {
+        // starts over from the initial puzzle with a fresh solver, keeping the view (zoom, pan, step mode etc) as-is
+        let mut fresh = PuzzleController::new(self.initial_puzzle.clone());
+        fresh.cursor_pos = self.cursor_pos;
+        fresh.window_size = self.window_size;
+        fresh.zoom = self.zoom;
+        fresh.pan = self.pan;
+        fresh.step_mode = self.step_mode;
+        fresh.speculate = self.speculate;
+        fresh.ctrl_down = self.ctrl_down;
+        fresh.state_path = self.state_path.clone();
+        fresh.message = Some("reset to the initial state".to_string());
+        *self = fresh;
+    }
+    pub fn save_state(&self, path: &Path) -> std::io::Result<()> {
+        // writes the solver's current grid state as ASCII art, in the format accepted by --resume
+        fs::write(path, self.solver.puzzle.ascii_state())
+    }
+    fn _mark_square(&mut self, square: Option<[usize;2]>, status: SquareStatus) {
+        // manually sets the status of the given square (if any), e.g. the one under the mouse cursor
+        let [x, y] = match square {
+            Some(pos) => pos,
+            None      => return,
+        };
+        self._show_pending_changes();
+        self.last_changes.clear();
+        self.message = match self.solver.set_square_status(x, y, status) {
+            Ok(Some(change)) => { self.last_changes.push(change); None },
+            Ok(None)         => None,
+            Err(e)           => Some(format!("can't mark square (x={}, y={}): {}", x, y, e)),
+        };
+    }
+    pub fn puzzle(&self) -> &Puzzle {
+        // the puzzle as it should currently be displayed; lags behind the solver's puzzle
+        // while the changes of an iteration are being shown one at a time.
+        self.shown_puzzle.as_ref().unwrap_or(&self.solver.puzzle)
+    }
+    fn _next_iteration(&mut self) -> Option<Result<Changes, Error>> {
+        // runs the next solver iteration and returns its changes. when speculating, probing, guesses and retractions
+        // count as iterations too; they're reported in the info panel, and only probing returns any changes.
+        if !self.speculate {
+            return self.solver.next().map(|result| result.map(|(_d, _i, changes)| changes));
+        }
+        self.solver.next_speculative(SquareStatus::FilledIn).map(|result| result.map(|step| match step {
+            SpeculativeStep::Iteration(_d, _i, changes) => changes,
+            SpeculativeStep::Probed(changes) => {
+                self.message = Some(format!("out of deductions; probing squares found {} changes", changes.len()));
+                changes
+            },
+            SpeculativeStep::Excluded(guesses) => {
+                self.message = Some(format!("out of deductions; {} guesses are known to fail, applied the inverse", guesses.len()));
+                vec![]
+            },
+            SpeculativeStep::Guessed(guess) => {
+                self.message = Some(format!("out of deductions; guessing {}", guess));
+                vec![]
+            },
+            SpeculativeStep::Retracted(guess) => {
+                self.message = Some(format!("guess {} led to a contradiction; retracted", guess));
+                vec![]
+            },
+        }))
+    }
+    fn step(&mut self) -> Option<Result<(), Error>> {
+        // advances by one step according to the step mode. returns None if the solver has run out of work,
+        // or the solver's error if the iteration failed.
+        self.last_changes.clear();
+        match self.step_mode {
+            StepMode::Iteration => {
+                self._show_pending_changes();
+                self._next_iteration().map(|result| result.map(|changes| {
+                    self.last_changes = changes;
+                }))
+            }
+            StepMode::SingleChange => {
+                let mut result = Some(Ok(()));
+                if self.pending_changes.is_empty() {
+                    // run the next iteration, but keep showing the puzzle as it was before it
+                    let snapshot = self.solver.puzzle.clone();
+                    match self._next_iteration() {
+                        Some(Ok(changes)) => {
+                            self.pending_changes.extend(changes);
+                            self.shown_puzzle = Some(snapshot);
+                        }
+                        Some(Err(e)) => { result = Some(Err(e)); }
+                        None         => { result = None; }
+                    }
+                }
+                if let Some(change) = self.pending_changes.pop_front() {
+                    if let Some(puzzle) = &mut self.shown_puzzle {
+                        // the solver already applied this change successfully, so it can't conflict here
+                        puzzle.apply_change(change.clone()).ok();
+                    }
+                    self.last_changes.push(change);
+                }
+                if self.pending_changes.is_empty() {
+                    self.shown_puzzle = None;
+                }
+                result
+            }
+        }
+    }
+    fn _auto_step(&mut self, dt: f64, interval: f64) {
+        // steps the solver once for every full interval that has passed while playing,
+        // and stops playing once the solver has nothing left to do or fails.
+        self.play_elapsed += dt;
+        while self.play && self.play_elapsed >= interval {
+            self.play_elapsed -= interval;
+            let stop_reason = match self.step() {
+                Some(Ok(()))  => continue,
+                Some(Err(e))  => format!("solver error: {}", e),
+                None if self.solver.puzzle.is_completed() => "puzzle solved".to_string(),
+                None          => "no more deductions".to_string(),
+            };
+            self.play = false;
+            self.play_stop_reason = Some(stop_reason);
         }
     }
-    pub fn event<E: GenericEvent>(&mut self, e: &E) {
+    fn _show_pending_changes(&mut self) {
+        // catches the displayed puzzle up with the solver
+        self.last_changes.extend(self.pending_changes.drain(..));
+        self.shown_puzzle = None;
+    }
+    pub fn event<E: GenericEvent>(&mut self, e: &E, view: &PuzzleView) {
+        if let Some(args) = e.update_args() {
+            if self.play {
+                self._auto_step(args.dt, view.settings.autoplay_interval_ms as f64 / 1000.0);
+            }
+        }
+        if let Some(Button::Keyboard(Key::LCtrl)) | Some(Button::Keyboard(Key::RCtrl)) = e.release_args() {
+            self.ctrl_down = false;
+        }
         if let Some(pos) = e.mouse_cursor_args() {
             self.cursor_pos = pos;
         }
+        if let Some(args) = e.render_args() {
+            self.window_size = args.window_size;
+        }
         if let Some(Button::Keyboard(key)) = e.press_args() {
             match key {
+                Key::LCtrl | Key::RCtrl => { self.ctrl_down = true; }
+                Key::S if self.ctrl_down => {
+                    let path = self.state_path.clone();
+                    self.message = Some(match self.save_state(&path) {
+                        Ok(())  => format!("saved grid state to {}", path.display()),
+                        Err(e)  => format!("failed to save grid state to {}: {}", path.display(), e),
+                    });
+                }
                 Key::S => {
                     // single-step the solver
-                    if let Some(iteration_result) = self.solver.next() {
-                        match iteration_result {
-                            Ok((_d,_i,_changes)) => { }
-                            Err(_) => { }
-                        }
+                    self.step();
+                }
+                Key::F => {
+                    let square = view.mouse_pos_to_square(self, self.cursor_pos);
+                    self._mark_square(square, SquareStatus::FilledIn);
+                }
+                Key::X => {
+                    let square = view.mouse_pos_to_square(self, self.cursor_pos);
+                    self._mark_square(square, SquareStatus::CrossedOut);
+                }
+                Key::Space => {
+                    self.play = !self.play;
+                    self.play_elapsed = 0.0;
+                    if self.play {
+                        self.play_stop_reason = None;
                     }
                 }
+                Key::Backspace => {
+                    self.reset();
+                }
+                Key::G => {
+                    self.speculate = !self.speculate;
+                }
+                Key::M => {
+                    self.step_mode = match self.step_mode {
+                        StepMode::Iteration    => StepMode::SingleChange,
+                        StepMode::SingleChange => { self._show_pending_changes(); StepMode::Iteration },
+                    };
+                }
+                Key::Left  => { self.pan[0] += PAN_STEP; }
+                Key::Right => { self.pan[0] -= PAN_STEP; }
+                Key::Up    => { self.pan[1] += PAN_STEP; }
+                Key::Down  => { self.pan[1] -= PAN_STEP; }
+                Key::Plus | Key::Equals | Key::NumPadPlus => { self.zoom *= ZOOM_STEP; }
+                Key::Minus | Key::NumPadMinus             => { self.zoom /= ZOOM_STEP; }
                 _ => {}
             }
         }
     }
 }
-struct PuzzleViewSettings {
+pub struct PuzzleViewSettings {
     pub position: [f64; 2],
-    pub subdivision_size: Option<usize>, // visual subdivision size (optional)
-    pub square_size: f64, // width and height of each square
+    pub subdivision_size: (Option<usize>, Option<usize>), // visual subdivision size for rows and cols (optional)
+    pub background_color: Color,
+    pub square_size: f64, // width and height of each square at which the font sizes below are used as-is
+    pub info_panel_width: f64, // space to keep free to the right of the grid for the info panel
 
     pub unknown_sq_fill_color: Color,
     pub unknown_sq_fill_color_hl: Color,
@@ -60,6 +286,10 @@ struct PuzzleViewSettings {
     pub filled_sq_fill_color_hl: Color,
     pub crossedout_sq_line_color: Color,
     pub crossedout_sq_line_thickness: f64,
+    pub changed_sq_border_color: Color, // border around squares that changed in the last step
+    pub changed_sq_border_thickness: f64,
+    pub placement_sq_overlay_color: Color, // shading over squares covered by the placements of the hovered run
+    pub guessed_sq_border_color: Color,    // border around squares whose status is a guess that's still in effect
 
     pub line_color: Color,
     pub square_line_thickness: f64, // line width for individual squares
@@ -75,13 +305,16 @@ struct PuzzleViewSettings {
     pub info_text_color: Color,
     pub info_text_line_height: f64,
 
+    pub autoplay_interval_ms: u64, // time between solver steps during auto-play
 }
 impl PuzzleViewSettings {
-    pub fn new(subdivision_size: Option<usize>) -> Self {
+    pub fn new(subdivision_size: (Option<usize>, Option<usize>)) -> Self {
         Self {
             position: [20.0; 2],
             subdivision_size,
+            background_color: [1.0, 1.0, 1.0, 1.0],
             square_size: 20.0,
+            info_panel_width: 320.0,
 
             unknown_sq_fill_color: [0.7, 0.7, 0.7, 1.0],
             unknown_sq_fill_color_hl: [0.8, 0.8, 0.8, 1.0],
@@ -89,6 +322,10 @@ impl PuzzleViewSettings {
             filled_sq_fill_color_hl: [138.0/255.0, 182.0/255.0, 1.0, 1.0], // highlight
             crossedout_sq_line_color: [0.8, 0.8, 0.8, 1.0],
             crossedout_sq_line_thickness: 0.75,
+            changed_sq_border_color: [236.0/255.0, 153.0/255.0, 23.0/255.0, 1.0],
+            changed_sq_border_thickness: 2.0,
+            placement_sq_overlay_color: [1.0, 0.6, 0.0, 0.35],
+            guessed_sq_border_color: [0.8, 0.1, 0.8, 1.0],
 
             line_color: [0.0, 0.0, 0.0, 1.0],
             square_line_thickness: 1.0,
@@ -104,8 +341,93 @@ impl PuzzleViewSettings {
             info_text_font_size: 16,
             info_text_color: [0.0, 0.0, 0.0, 1.0],
             info_text_line_height: 20.0,
+
+            autoplay_interval_ms: 100,
         }
     }
+    pub fn light_theme(subdivision_size: (Option<usize>, Option<usize>)) -> Self {
+        // the default look: dark lines and text on a white background
+        Self::new(subdivision_size)
+    }
+    pub fn dark_theme(subdivision_size: (Option<usize>, Option<usize>)) -> Self {
+        // light lines and text on a dark background
+        Self::new(subdivision_size)
+            .with_background_color([0.12, 0.12, 0.14, 1.0])
+            .with_line_color([0.85, 0.85, 0.85, 1.0])
+            .with_unknown_color([0.25, 0.25, 0.28, 1.0], [0.35, 0.35, 0.38, 1.0])
+            .with_filled_color([99.0/255.0, 128.0/255.0, 1.0, 1.0], [138.0/255.0, 182.0/255.0, 1.0, 1.0])
+            .with_crossedout_color([0.45, 0.45, 0.45, 1.0])
+            .with_text_color([0.9, 0.9, 0.9, 1.0])
+            .with_completed_run_text_color([0.45, 0.45, 0.45, 1.0])
+    }
+
+    // builder-style overrides of individual settings, for theming
+    pub fn with_square_size(mut self, square_size: f64) -> Self {
+        self.square_size = square_size;
+        self
+    }
+    pub fn with_background_color(mut self, color: Color) -> Self {
+        self.background_color = color;
+        self
+    }
+    pub fn with_line_color(mut self, color: Color) -> Self {
+        self.line_color = color;
+        self
+    }
+    pub fn with_unknown_color(mut self, color: Color, highlight_color: Color) -> Self {
+        self.unknown_sq_fill_color = color;
+        self.unknown_sq_fill_color_hl = highlight_color;
+        self
+    }
+    pub fn with_filled_color(mut self, color: Color, highlight_color: Color) -> Self {
+        self.filled_sq_fill_color = color;
+        self.filled_sq_fill_color_hl = highlight_color;
+        self
+    }
+    pub fn with_crossedout_color(mut self, color: Color) -> Self {
+        self.crossedout_sq_line_color = color;
+        self
+    }
+    pub fn with_changed_border_color(mut self, color: Color) -> Self {
+        self.changed_sq_border_color = color;
+        self
+    }
+    pub fn with_placement_overlay_color(mut self, color: Color) -> Self {
+        self.placement_sq_overlay_color = color;
+        self
+    }
+    pub fn with_guessed_border_color(mut self, color: Color) -> Self {
+        self.guessed_sq_border_color = color;
+        self
+    }
+    pub fn with_text_color(mut self, color: Color) -> Self {
+        // color of the info panel text and of the clues of runs that aren't completed yet
+        self.info_text_color = color;
+        self.run_text_color_incomplete = color;
+        self
+    }
+    pub fn with_completed_run_text_color(mut self, color: Color) -> Self {
+        self.run_text_color_complete = color;
+        self
+    }
+    pub fn with_highlighted_run_text_color(mut self, color: Color) -> Self {
+        self.run_text_color_hl = color;
+        self
+    }
+    pub fn with_font_sizes(mut self, run_text_font_size: u32, info_text_font_size: u32) -> Self {
+        self.run_text_font_size = run_text_font_size;
+        self.info_text_font_size = info_text_font_size;
+        self
+    }
+    pub fn with_autoplay_interval_ms(mut self, interval_ms: u64) -> Self {
+        self.autoplay_interval_ms = interval_ms;
+        self
+    }
+}
+struct Layout {
+    pub square_size: f64,
+    pub runarea_drawwidth: f64,  // width of the runs block to the left of the grid
+    pub runarea_drawheight: f64, // height of the runs block to the top of the grid
 }
 struct PuzzleView {
     pub settings: PuzzleViewSettings,
@@ -114,22 +436,42 @@ impl PuzzleView {
     pub fn new(settings: PuzzleViewSettings) -> Self {
         Self { settings }
     }
-    pub fn mouse_pos_to_square(&self, puzzle: &Puzzle, pos: [f64; 2])
+    pub fn layout(&self, controller: &PuzzleController) -> Layout {
+        // picks the square size at which the whole puzzle (including run areas and the info panel)
+        // fits inside the window, applies the controller's zoom factor on top of it, and determines
+        // the size of the run areas accordingly.
+        let puzzle = controller.puzzle();
+        let num_h_runs = puzzle.rows.iter().map(|row| row.runs().len()).max().unwrap();
+        let num_v_runs = puzzle.cols.iter().map(|col| col.runs().len()).max().unwrap();
+        let available_width  = controller.window_size[0] - 2.0*self.settings.position[0] - self.settings.info_panel_width;
+        let available_height = controller.window_size[1] - 2.0*self.settings.position[1];
+
+        let fit_size = f64::min(available_width  / ((num_h_runs + puzzle.width()) as f64),
+                                available_height / ((num_v_runs + puzzle.height()) as f64));
+        let square_size = f64::max(fit_size, 1.0) * controller.zoom;
+        Layout {
+            square_size,
+            runarea_drawwidth:  (num_h_runs as f64) * square_size,
+            runarea_drawheight: (num_v_runs as f64) * square_size,
+        }
+    }
+    pub fn font_size(&self, font_size: u32, square_size: f64) -> u32 {
+        // scales a font size from the settings along with the current square size
+        f64::max(1.0, ((font_size as f64) * square_size / self.settings.square_size).round()) as u32
+    }
+    pub fn mouse_pos_to_square(&self, controller: &PuzzleController, pos: [f64; 2])
         -> Option<[usize;2]>
     {
         // given a mouse position (in absolute coordinates), returns the (x,y) indices of the corresponding
         // square (if any) in the given puzzle.
         
-        // the square grid starts at self.settings.position + the width and height of the run areas
-        // TODO: code duplication with draw()
-        let square_size: f64 = self.settings.square_size;
-        let num_h_runs: usize = puzzle.rows.iter().map(|row| row.runs.len()).max().unwrap();
-        let num_v_runs: usize = puzzle.cols.iter().map(|col| col.runs.len()).max().unwrap();
-        let runarea_drawwidth: f64  = (num_h_runs as f64) * square_size; // width of the runs block to the left of the grid
-        let runarea_drawheight: f64 = (num_v_runs as f64) * square_size; // width of the runs block to the top of the grid
-
-        let grid_xoffset = self.settings.position[0] + runarea_drawwidth;
-        let grid_yoffset = self.settings.position[1] + runarea_drawheight;
+        // the square grid starts at self.settings.position + the pan offset + the width and height of the run areas
+        let puzzle = controller.puzzle();
+        let layout = self.layout(controller);
+        let square_size = layout.square_size;
+
+        let grid_xoffset = self.settings.position[0] + controller.pan[0] + layout.runarea_drawwidth;
+        let grid_yoffset = self.settings.position[1] + controller.pan[1] + layout.runarea_drawheight;
         let mouse_x_relative = pos[0] - grid_xoffset; // relative to the top left corner of the drawn grid
         let mouse_y_relative = pos[1] - grid_yoffset;
 
@@ -144,16 +486,71 @@ impl PuzzleView {
         }
         
     }
+    pub fn mouse_pos_to_run(&self, controller: &PuzzleController, layout: &Layout, pos: [f64; 2])
+        -> Option<(Direction, usize, usize)>
+    {
+        // given a mouse position (in absolute coordinates), returns the direction, row/col index and run index
+        // of the clue number under it (if any). clues are right-aligned in the row run area and bottom-aligned
+        // in the column run area, one square-sized slot per run.
+        let puzzle = controller.puzzle();
+        let square_size = layout.square_size;
+
+        let runarea_xoffset = self.settings.position[0] + controller.pan[0];
+        let runarea_yoffset = self.settings.position[1] + controller.pan[1];
+        let mouse_x_relative = pos[0] - runarea_xoffset; // relative to the top left corner of the whole drawing
+        let mouse_y_relative = pos[1] - runarea_yoffset;
+
+        let (direction, row_idx, slot) = if mouse_x_relative >= 0.0 && mouse_x_relative < layout.runarea_drawwidth &&
+                                            mouse_y_relative >= layout.runarea_drawheight
+        {
+            let row_idx = ((mouse_y_relative - layout.runarea_drawheight) / square_size).floor() as usize;
+            let slot = ((layout.runarea_drawwidth - mouse_x_relative) / square_size).floor() as usize;
+            (Horizontal, row_idx, slot)
+        } else if mouse_y_relative >= 0.0 && mouse_y_relative < layout.runarea_drawheight &&
+                  mouse_x_relative >= layout.runarea_drawwidth
+        {
+            let col_idx = ((mouse_x_relative - layout.runarea_drawwidth) / square_size).floor() as usize;
+            let slot = ((layout.runarea_drawheight - mouse_y_relative) / square_size).floor() as usize;
+            (Vertical, col_idx, slot)
+        } else {
+            return None;
+        };
+
+        let rows = match direction {
+            Horizontal => &puzzle.rows,
+            Vertical   => &puzzle.cols,
+        };
+        let run_count = rows.get(row_idx)?.runs().len();
+        if slot < run_count {
+            Some((direction, row_idx, run_count - 1 - slot))
+        } else {
+            None
+        }
+    }
+    fn hovered_run_covers_square(&self, controller: &PuzzleController, hovered_run: Option<(Direction, usize, usize)>,
+                                 x: usize, y: usize) -> bool
+    {
+        // whether square (x,y) lies within any of the remaining placements of the run whose clue is under the mouse
+        let puzzle = controller.puzzle();
+        match hovered_run {
+            Some((Horizontal, row_idx, run_idx)) if row_idx == y =>
+                puzzle.rows[row_idx].runs()[run_idx].placements().iter().any(|range| range.contains(&x)),
+            Some((Vertical, col_idx, run_idx)) if col_idx == x =>
+                puzzle.cols[col_idx].runs()[run_idx].placements().iter().any(|range| range.contains(&y)),
+            _ => false,
+        }
+    }
     pub fn draw_h_runs<G: Graphics, C>(&self, row: &Row,
-                                              draw_width: f64,
+                                              layout: &Layout,
                                               highlighted_idx: Option<usize>,
                                               c: &Context,
                                               glyphs: &mut C,
                                               g: &mut G)
         where C: CharacterCache<Texture = G::Texture>
     {
-        let square_size = self.settings.square_size;
-        for (n,run) in row.runs.iter().rev().enumerate() {
+        let square_size = layout.square_size;
+        let draw_width = layout.runarea_drawwidth;
+        for (n,run) in row.runs().iter().rev().enumerate() {
             let mut text_color = match run.is_completed() {
                 true  => self.settings.run_text_color_complete,
                 false => self.settings.run_text_color_incomplete,
@@ -161,26 +558,28 @@ impl PuzzleView {
             if let Some(h_idx) = highlighted_idx {
                 if run.index == h_idx { text_color = self.settings.run_text_color_hl; }
             }
-            let text_style = Text::new_color(text_color, self.settings.run_text_font_size);
+            let text_style = Text::new_color(text_color, self.font_size(self.settings.run_text_font_size, square_size));
+            let text = run.length.to_string();
 
-            let mut x = draw_width - square_size/4.0 - ((n+1) as f64) * square_size; // subtract a little extra for visual margin
+            let slot_x = draw_width - ((n+1) as f64) * square_size;
+            let x = slot_x + Self::centering_offset(&text, text_style.font_size, square_size, glyphs);
             let y = ((row.index + 1) as f64) * square_size; // text y position is on bottom left, not top left
-            if run.length < 10 { x += square_size/4.0; } // move single-char numbers over a bit
             let c = c.trans(x, y-(square_size/6.0)); // move text up a little bit for visual
-            text_style.draw(&run.length.to_string(), glyphs, &c.draw_state, c.transform, g)
+            text_style.draw(&text, glyphs, &c.draw_state, c.transform, g)
                       .ok().unwrap();
         }
     }
     pub fn draw_v_runs<G: Graphics, C>(&self, row: &Row,
-                                              draw_height: f64,
+                                              layout: &Layout,
                                               highlighted_idx: Option<usize>,
                                               c: &Context,
                                               glyphs: &mut C,
                                               g: &mut G)
         where C: CharacterCache<Texture = G::Texture>
     {
-        let square_size = self.settings.square_size;
-        for (i,run) in row.runs.iter().rev().enumerate() {
+        let square_size = layout.square_size;
+        let draw_height = layout.runarea_drawheight;
+        for (i,run) in row.runs().iter().rev().enumerate() {
             let mut text_color = match run.is_completed() {
                 true  => self.settings.run_text_color_complete,
                 false => self.settings.run_text_color_incomplete,
@@ -188,28 +587,37 @@ impl PuzzleView {
             if let Some(h_idx) = highlighted_idx {
                 if run.index == h_idx { text_color = self.settings.run_text_color_hl; }
             }
-            let text_style = Text::new_color(text_color, self.settings.run_text_font_size);
+            let text_style = Text::new_color(text_color, self.font_size(self.settings.run_text_font_size, square_size));
+            let text = run.length.to_string();
 
-            let mut x = (row.index as f64) * square_size;
+            let slot_x = (row.index as f64) * square_size;
+            let x = slot_x + Self::centering_offset(&text, text_style.font_size, square_size, glyphs);
             let y = draw_height - square_size/4.0 - (i as f64) * square_size;
-            if run.length < 10 { x += square_size/4.0; } // move single-char numbers over a bit
             let c = c.trans(x, y);
-            text_style.draw(&run.length.to_string(), glyphs, &c.draw_state, c.transform, g)
+            text_style.draw(&text, glyphs, &c.draw_state, c.transform, g)
                       .ok().unwrap();
         }
     }
-    pub fn draw_square<G: Graphics>(&self, x: usize,
-                                           y: usize,
+    fn centering_offset<C: CharacterCache>(text: &str, font_size: FontSize, slot_width: f64, glyphs: &mut C) -> f64 {
+        // horizontal offset that centers the rendered text within a slot, regardless of how many digits it has
+        let text_width = glyphs.width(font_size, text).unwrap_or(0.0);
+        ((slot_width - text_width) / 2.0).max(0.0)
+    }
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_square<G: Graphics>(&self, pos: [usize; 2],
                                            is_highlighted: bool,
+                                           hovered_run: Option<(Direction, usize, usize)>,
+                                           layout: &Layout,
                                            controller: &PuzzleController,
                                            c: &Context,
                                            g: &mut G)
     {
         // note: we're in a translated context, so we can draw our square starting at (0,0) in the top left
-        let square_size = self.settings.square_size;
+        let [x, y] = pos;
+        let square_size = layout.square_size;
         let square_rect = [0.0, 0.0, square_size, square_size];
 
-        let square = controller.solver.puzzle.get_square(x, y);
+        let square = controller.puzzle().get_square(x, y);
         match square.get_status() {
             SquareStatus::FilledIn   => {
                 let fill_style = Rectangle::new(match is_highlighted {
@@ -235,15 +643,36 @@ impl PuzzleView {
             }
         }
 
+        // shade squares that the hovered clue's run could still be placed over
+        if self.hovered_run_covers_square(controller, hovered_run, x, y) {
+            let overlay_style = Rectangle::new(self.settings.placement_sq_overlay_color);
+            overlay_style.draw(square_rect, &c.draw_state, c.transform, g);
+        }
+
         // if the square has known vertical or horizontal runs, draw a small indicator line to signify this
         if let Some(_) = square.get_run_index(Horizontal) {
-            let line_style = Line::new([0.0, 0.0, 0.0, 1.0], 0.5);
+            let line_style = Line::new(self.settings.line_color, 0.5);
             line_style.draw([0.0, square_size/2.0, square_size/2.0 * 0.8, square_size/2.0], &c.draw_state, c.transform, g);
         }
         if let Some(_) = square.get_run_index(Vertical) {
-            let line_style = Line::new([0.0, 0.0, 0.0, 1.0], 0.5);
+            let line_style = Line::new(self.settings.line_color, 0.5);
             line_style.draw([square_size/2.0, 0.0, square_size/2.0, square_size/2.0 * 0.8], &c.draw_state, c.transform, g);
         }
+
+        // outline squares whose status is a guess that's still in effect, or that were changed by the last solver step
+        let border_color = if controller.solver.guesses().any(|guess| guess.x == x && guess.y == y) {
+            Some(self.settings.guessed_sq_border_color)
+        } else if controller.last_changes.iter().any(|change| change.get_col() == x && change.get_row() == y) {
+            Some(self.settings.changed_sq_border_color)
+        } else {
+            None
+        };
+        if let Some(border_color) = border_color {
+            let radius = self.settings.changed_sq_border_thickness/2.0; // border radius = HALF of line thickness!
+            let border_style = Rectangle::new_border(border_color, radius);
+            border_style.draw([radius, radius, square_size - 2.0*radius, square_size - 2.0*radius],
+                              &c.draw_state, c.transform, g);
+        }
     }
     pub fn draw<G: Graphics, C>(&self, controller: &PuzzleController,
                                        c: &Context,
@@ -284,25 +713,27 @@ impl PuzzleView {
         // note:
         // line [x1, x2, y1, y2],
         //  => radius is HALF the line thickness!
-        let c = c.trans(settings.position[0], settings.position[1]);
+        let c = c.trans(settings.position[0] + controller.pan[0],
+                        settings.position[1] + controller.pan[1]);
 
-        let subdivision_size = settings.subdivision_size.unwrap_or(0usize);
-        let square_size = settings.square_size;
-        let puzzle = &controller.solver.puzzle;
+        let row_subdivision_size = settings.subdivision_size.0.unwrap_or(0usize);
+        let col_subdivision_size = settings.subdivision_size.1.unwrap_or(0usize);
+        let layout = self.layout(controller);
+        let square_size = layout.square_size;
+        let puzzle = controller.puzzle();
 
         // rectangles are specified by: [x, y, w, h]
         // lines are specified by: [x1, y1, x2, y2]
-        let num_h_runs = puzzle.rows.iter().map(|row| row.runs.len()).max().unwrap();
-        let num_v_runs = puzzle.cols.iter().map(|col| col.runs.len()).max().unwrap();
-        let runarea_drawwidth = (num_h_runs as f64) * square_size; // width of the runs block to the left of the grid
-        let runarea_drawheight = (num_v_runs as f64) * square_size; // width of the runs block to the top of the grid
+        let runarea_drawwidth = layout.runarea_drawwidth;
+        let runarea_drawheight = layout.runarea_drawheight;
 
         let grid_xoffset = runarea_drawwidth;
         let grid_yoffset = runarea_drawheight;
         let grid_drawwidth  = (puzzle.width() as f64) * square_size;
         let grid_drawheight = (puzzle.height() as f64) * square_size;
 
-        let highlighted_sq_pos = self.mouse_pos_to_square(&controller.solver.puzzle, controller.cursor_pos);
+        let highlighted_sq_pos = self.mouse_pos_to_square(controller, controller.cursor_pos);
+        let hovered_run = self.mouse_pos_to_run(controller, &layout, controller.cursor_pos);
 
         // draw squares
         for y in 0..puzzle.height() {
@@ -310,7 +741,7 @@ impl PuzzleView {
                 let is_highlighted = highlighted_sq_pos.map(|[hx, hy]| hx == x && hy == y).unwrap_or(false);
                 let c = c.trans(grid_xoffset + (x as f64)*square_size,
                                 grid_yoffset + (y as f64)*square_size);
-                self.draw_square(x, y, is_highlighted, controller, &c, g);
+                self.draw_square([x, y], is_highlighted, hovered_run, &layout, controller, &c, g);
             }
         }
 
@@ -320,20 +751,26 @@ impl PuzzleView {
             let mut highlighted_run_idx: Option<usize> = None;
             if let Some([hx,hy]) = highlighted_sq_pos {
                 if row_idx == hy {
-                    highlighted_run_idx = puzzle.get_square(hx, hy).get_run_index(row.direction);
+                    highlighted_run_idx = row.run_at(hx).map(|run| run.index);
                 }
             }
-            self.draw_h_runs(row, runarea_drawwidth, highlighted_run_idx, &c.trans(0.0, grid_yoffset), glyphs, g);
+            if let Some((Horizontal, hovered_row_idx, run_idx)) = hovered_run {
+                if row_idx == hovered_row_idx { highlighted_run_idx = Some(run_idx); }
+            }
+            self.draw_h_runs(row, &layout, highlighted_run_idx, &c.trans(0.0, grid_yoffset), glyphs, g);
         }
         for col_idx in 0..puzzle.width() {
             let col = &puzzle.cols[col_idx];
             let mut highlighted_run_idx: Option<usize> = None;
             if let Some([hx,hy]) = highlighted_sq_pos {
                 if col_idx == hx {
-                    highlighted_run_idx = puzzle.get_square(hx, hy).get_run_index(col.direction);
+                    highlighted_run_idx = col.run_at(hy).map(|run| run.index);
                 }
             }
-            self.draw_v_runs(col, runarea_drawheight, highlighted_run_idx, &c.trans(grid_xoffset, 0.0), glyphs, g);
+            if let Some((Vertical, hovered_col_idx, run_idx)) = hovered_run {
+                if col_idx == hovered_col_idx { highlighted_run_idx = Some(run_idx); }
+            }
+            self.draw_v_runs(col, &layout, highlighted_run_idx, &c.trans(grid_xoffset, 0.0), glyphs, g);
         }
 
         // draw grid
@@ -347,9 +784,9 @@ impl PuzzleView {
                 let line_coords = [0.0, y, runarea_drawwidth + grid_drawwidth, y];
 
                 let style = match i {
-                    a if a == 0 || a == puzzle.height()                     => &grid_outline_style,
-                    a if subdivision_size > 0 && a % subdivision_size == 0  => &subdivision_line_style,
-                    _                                                       => &square_line_style,
+                    a if a == 0 || a == puzzle.height()                             => &grid_outline_style,
+                    a if row_subdivision_size > 0 && a % row_subdivision_size == 0  => &subdivision_line_style,
+                    _                                                               => &square_line_style,
                 };
                 style.draw(line_coords, &c.draw_state, c.transform, g);
             }
@@ -358,9 +795,9 @@ impl PuzzleView {
                 let line_coords = [x, 0.0, x, runarea_drawheight + grid_drawheight];
 
                 let style = match i {
-                    a if a == 0 || a == puzzle.width()                      => &grid_outline_style,
-                    a if subdivision_size > 0 && a % subdivision_size == 0  => &subdivision_line_style,
-                    _                                                       => &square_line_style,
+                    a if a == 0 || a == puzzle.width()                              => &grid_outline_style,
+                    a if col_subdivision_size > 0 && a % col_subdivision_size == 0  => &subdivision_line_style,
+                    _                                                               => &square_line_style,
                 };
                 style.draw(line_coords, &c.draw_state, c.transform, g);
             }
@@ -370,28 +807,73 @@ impl PuzzleView {
         {
             let c = c.trans(grid_xoffset + grid_drawwidth, 0.0);
             let c = c.trans(square_size, 0.0); // some extra spacing
-            let text_style = Text::new_color([0.0, 0.0, 0.0, 1.0], settings.info_text_font_size);
+            let text_style = Text::new_color(settings.info_text_color, settings.info_text_font_size);
 
             let num_squares_total = puzzle.height() * puzzle.width();
-            let num_squares_known = puzzle.rows.iter().fold(0, |acc, row| acc + (0..row.length).filter(|&pos| row.get_square(pos).get_status() != SquareStatus::Unknown)
-                                                                                               .count());
+            let num_squares_known = puzzle.num_known_squares();
             let state_text = format!(
 r"Completion: {}/{}
 Iterations: {}
-
-Press S to single-step the solver.", num_squares_known, num_squares_total,
-                                     controller.solver.iterations);
+Step mode: {} ({} changes pending)
+Auto-play: {}
+Speculation: {}
+{}
+
+Press S to single-step the solver, M to switch step mode.
+Press Space to start or stop auto-play, G to toggle speculation.
+Press F or X to fill in or cross out the square under the cursor.
+Press Ctrl+S to save the grid state, Backspace to start over.
+Arrow keys pan, +/- zoom in and out.", num_squares_known, num_squares_total,
+                                     controller.solver.iterations,
+                                     controller.step_mode, controller.pending_changes.len(),
+                                     match (controller.play, &controller.play_stop_reason) {
+                                         (true, _)             => "playing".to_string(),
+                                         (false, Some(reason)) => format!("stopped ({})", reason),
+                                         (false, None)         => "paused".to_string(),
+                                     },
+                                     match controller.speculate {
+                                         true  => "on",
+                                         false => "off",
+                                     },
+                                     controller.message.as_deref().unwrap_or(""));
             for (i, line) in state_text.split("\n").enumerate() {
                 let c = c.trans(0.0, (i as f64) * settings.info_text_line_height);
                 text_style.draw(line, glyphs, &c.draw_state, c.transform, g).ok().unwrap();
             }
+
+            // per-line progress, below the text above (with an empty line in between)
+            let num_rows_completed = puzzle.rows.iter().filter(|row| row.is_completed()).count();
+            let num_cols_completed = puzzle.cols.iter().filter(|col| col.is_completed()).count();
+            let lines_text = format!(
+r"Rows completed: {}/{}
+Columns completed: {}/{}
+Lines queued: {}
+Guesses: {}", num_rows_completed, puzzle.rows.len(),
+                   num_cols_completed, puzzle.cols.len(),
+                   controller.solver.queue_len(),
+                   controller.solver.guesses().count());
+            // the stack of guesses currently in effect, oldest at the top
+            let lines_text = controller.solver.guesses()
+                                              .enumerate()
+                                              .fold(lines_text, |text, (depth, guess)| format!("{}\n  {}. {}", text, depth + 1, guess));
+            let c = c.trans(0.0, ((state_text.split("\n").count() + 1) as f64) * settings.info_text_line_height);
+            for (i, line) in lines_text.split("\n").enumerate() {
+                let c = c.trans(0.0, (i as f64) * settings.info_text_line_height);
+                text_style.draw(line, glyphs, &c.draw_state, c.transform, g).ok().unwrap();
+            }
         }
 
     }
 }
 
-pub fn ui_main(puzzle: Puzzle, args: &Args)
+pub fn ui_main(puzzle: Puzzle, render_options: &RenderOptions, font: Option<&str>, state_path: &Path)
+{
+    ui_main_with_settings(puzzle, PuzzleViewSettings::light_theme(render_options.subdivision), font, state_path)
+}
+
+pub fn ui_main_with_settings(puzzle: Puzzle, puzzle_view_settings: PuzzleViewSettings, font: Option<&str>, state_path: &Path)
 {
+    // same as ui_main, but drawing the puzzle with the given view settings, e.g. one of the theme presets
     let opengl_version = OpenGL::V3_2;
     let settings = WindowSettings::new("Nonogram", [1200, 800])
                                    .graphics_api(opengl_version)
@@ -402,18 +884,25 @@ pub fn ui_main(puzzle: Puzzle, args: &Args)
     let mut gl = GlGraphics::new(opengl_version);
 
     let mut puzzle_controller = PuzzleController::new(puzzle);
-    let puzzle_view_settings = PuzzleViewSettings::new(args.visual_groups);
+    puzzle_controller.state_path = state_path.to_path_buf();
     let puzzle_view = PuzzleView::new(puzzle_view_settings);
 
     let texture_settings = TextureSettings::new().filter(Filter::Nearest);
-    let mut glyphs = GlyphCache::new("FiraSans-Regular.ttf", (), texture_settings)
-        .expect("Could not load font");
+    let builtin_glyphs = || GlyphCache::from_bytes(BUILTIN_FONT, (), texture_settings)
+                                       .expect("Could not load built-in font");
+    let mut glyphs = match font {
+        Some(path) => GlyphCache::new(path, (), texture_settings).unwrap_or_else(|e| {
+            warn!("could not load font {}: {}; falling back to the built-in font", path, e);
+            builtin_glyphs()
+        }),
+        None => builtin_glyphs(),
+    };
 
     while let Some(e) = events.next(&mut window) {
-        puzzle_controller.event(&e);
+        puzzle_controller.event(&e, &puzzle_view);
         if let Some(ev_args) = e.render_args() {
             gl.draw(ev_args.viewport(), |c, g| {
-                clear([1.0;4], g);
+                clear(puzzle_view.settings.background_color, g);
                 puzzle_view.draw(&puzzle_controller, &c, &mut glyphs, g);
             });
         }
@@ -1,10 +1,7 @@
 // vim: set ai et ts=4 sw=4 sts=4:
 use std::fmt;
-use std::io;
 use std::convert::TryFrom;
 use std::os::unix::io::AsRawFd;
-use std::rc::Rc;
-use std::cell::RefCell;
 use ansi_term::ANSIString;
 
 pub fn maybe_color(s: &ANSIString, emit_color: bool) -> String {
@@ -32,7 +29,7 @@ pub fn ralign_joined_coloreds(strs: &Vec<ANSIString>, width: usize, emit_color:
     -> String
 {
     let mut visual_len: usize = strs.iter().map(|ansi_str| ansi_str.len()).sum(); // ANSIString.len() returns length WITHOUT escape sequences
-    visual_len += strs.len()-1; // count the spaces that .join(" ") will add
+    visual_len += strs.len().saturating_sub(1); // count the spaces that .join(" ") will add (none for rows without clues)
 
     let joined_colored = strs.iter()
                              .map(|astr| maybe_color(astr, emit_color))
@@ -59,6 +56,14 @@ impl fmt::Display for Direction {
         })
     }
 }
+impl Direction {
+    pub fn orthogonal(&self) -> Direction {
+        match self {
+            Direction::Horizontal => Direction::Vertical,
+            Direction::Vertical   => Direction::Horizontal,
+        }
+    }
+}
 impl TryFrom<&str> for Direction {
     type Error = &'static str;
     fn try_from(value: &str) -> Result<Self, Self::Error> {
@@ -76,6 +81,39 @@ pub fn is_a_tty<T: AsRawFd>(handle: T) -> bool {
     unsafe { libc::isatty(fd) != 0 }
 }
 
+pub fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+     .replace('<', "&lt;")
+     .replace('>', "&gt;")
+     .replace('"', "&quot;")
+}
+
+pub struct Rng {
+    // small xorshift64* pseudo-random number generator, for reproducible randomized choices
+    state: u64,
+}
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // scramble the seed (splitmix64) so that small or similar seeds still give unrelated sequences,
+        // and so that the state is never 0 (which xorshift can't get out of)
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Rng { state: if z == 0 { 1 } else { z } }
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+    pub fn gen_below(&mut self, n: usize) -> usize {
+        // random number in 0..n; n must be non-zero
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
 pub fn vec_remove_item<T: PartialEq>(vec: &mut Vec<T>, item: &T) -> Option<T> {
     let pos = vec.iter().position(|x| *x == *item)?;
     Some(vec.remove(pos))
@@ -0,0 +1,86 @@
+// vim: set ai et ts=4 sts=4 sw=4:
+use super::puzzle::{Puzzle, Solver};
+use super::grid::SquareStatus;
+
+/// Browser-facing wrapper around `Solver`, meant to be exported through wasm-bindgen.
+///
+/// Everything it takes and returns is a plain string, number or byte array, so that it can cross the
+/// JavaScript boundary as is. Exposed functions:
+///  * `from_yaml(&str)`: constructs a solver for the first puzzle in a YAML puzzle definition.
+///  * `step()`: runs a single solver step, guessing when logic runs out; returns false once there's nothing left to do.
+///  * `is_completed()`: whether the puzzle has been solved completely.
+///  * `width()`, `height()`: dimensions of the puzzle grid.
+///  * `grid_state()`: flat row-major array of square statuses (0 = unknown, 1 = filled in, 2 = crossed out).
+///
+/// The `#[wasm_bindgen]` attributes themselves still need the wasm-bindgen crate added as a dependency of the
+/// `wasm` feature; until then, this type can be driven natively with the same API.
+pub struct WasmSolver {
+    solver: Solver,
+}
+impl WasmSolver {
+    /// Parses the given YAML puzzle definition and sets up a solver for its first puzzle.
+    /// Fails with a description of the problem if the definition is malformed or contains no puzzles.
+    pub fn from_yaml(yaml: &str) -> Result<WasmSolver, String> {
+        let puzzle = Puzzle::all_from_yaml(yaml).map_err(|e| e.to_string())?
+                                                .into_iter()
+                                                .next()
+                                                .ok_or_else(|| "Puzzle definition contains no puzzles".to_string())?;
+        Ok(WasmSolver { solver: Solver::new(puzzle) })
+    }
+    /// Runs a single solver step. Returns false once the puzzle is completed, or if it turns out to have no solution.
+    pub fn step(&mut self) -> bool {
+        matches!(self.solver.next_speculative(SquareStatus::FilledIn), Some(Ok(_)))
+    }
+    /// Whether every square of the puzzle is known.
+    pub fn is_completed(&self) -> bool {
+        self.solver.puzzle.is_completed()
+    }
+    /// Width of the puzzle grid, in squares.
+    pub fn width(&self) -> usize {
+        self.solver.puzzle.width()
+    }
+    /// Height of the puzzle grid, in squares.
+    pub fn height(&self) -> usize {
+        self.solver.puzzle.height()
+    }
+    /// Status of every square in row-major order: 0 for unknown, 1 for filled in, 2 for crossed out.
+    pub fn grid_state(&self) -> Vec<u8> {
+        self.solver.puzzle.status_matrix()
+                          .into_iter()
+                          .flatten()
+                          .map(|status| match status {
+                              SquareStatus::Unknown    => 0u8,
+                              SquareStatus::FilledIn   => 1u8,
+                              SquareStatus::CrossedOut => 2u8,
+                          })
+                          .collect()
+    }
+}
+
+#[cfg(all(test, feature = "wasm"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_until_solved() {
+        // solution:
+        //   ##.
+        //   .#.
+        //   ###
+        let mut solver = WasmSolver::from_yaml("rows: [2, 1, 3]\ncols: [[1, 1], 3, 1]").unwrap();
+        assert_eq!((solver.width(), solver.height()), (3, 3));
+        assert_eq!(solver.grid_state(), vec![0; 9]);
+        while solver.step() {}
+        assert!(solver.is_completed());
+        assert_eq!(solver.grid_state(), vec![1, 1, 2,
+                                             2, 1, 2,
+                                             1, 1, 1]);
+    }
+
+    #[test]
+    fn malformed_definitions_are_errors() {
+        assert!(WasmSolver::from_yaml("rows: [2, 1, 3]").is_err());
+        assert!(WasmSolver::from_yaml("").is_err());
+        assert!(WasmSolver::from_yaml("rows: [\n").is_err());
+    }
+}
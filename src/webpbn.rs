@@ -0,0 +1,127 @@
+// vim: set ai et ts=4 sts=4 sw=4:
+use xml::reader::{EventReader, XmlEvent};
+
+use super::puzzle::Puzzle;
+use super::grid::Error;
+
+/// Support for importing puzzles in the XML format exported by webpbn.com.
+///
+/// The clues are listed per line as `<count>` elements, inside one `<clues type="rows">` and one
+/// `<clues type="columns">` element. Only the first `<puzzle>` of a `<puzzleset>` is read; everything
+/// else (title, author, colors, solution, ...) is skipped over.
+impl Puzzle {
+    pub fn from_webpbn_xml(xml: &str) -> Result<Puzzle, Error> {
+        let mut row_clues: Option<Vec<Vec<usize>>> = None;
+        let mut col_clues: Option<Vec<Vec<usize>>> = None;
+        let mut clues: Option<(String, Vec<Vec<usize>>)> = None; // type and lines of the <clues> element we're in, if any
+        let mut line: Option<Vec<usize>> = None;                 // counts of the <line> element we're in, if any
+        let mut count_text: Option<String> = None;               // text of the <count> element we're in, if any
+
+        for event in EventReader::from_str(xml) {
+            let event = event.map_err(|e| Error::Logic(format!("Failed to parse webpbn XML: {}", e)))?;
+            match event {
+                XmlEvent::StartElement { name, attributes, .. } => match name.local_name.as_str() {
+                    "clues" => {
+                        let clue_type = attributes.iter()
+                                                  .find(|attr| attr.name.local_name == "type")
+                                                  .map(|attr| attr.value.clone())
+                                                  .ok_or_else(|| Error::Logic("webpbn <clues> element without a type".to_string()))?;
+                        clues = Some((clue_type, Vec::new()));
+                    },
+                    "line"  if clues.is_some() => line = Some(Vec::new()),
+                    "count" if line.is_some()  => count_text = Some(String::new()),
+                    _ => {},
+                },
+                XmlEvent::Characters(text) => {
+                    if let Some(count_text) = &mut count_text {
+                        count_text.push_str(&text);
+                    }
+                },
+                XmlEvent::EndElement { name } => match name.local_name.as_str() {
+                    "count" => if let (Some(text), Some(line)) = (count_text.take(), &mut line) {
+                        let count = text.trim().parse::<usize>().map_err(|_| Error::Logic(format!(
+                            "Invalid run length {:?} in webpbn XML", text.trim())))?;
+                        line.push(count);
+                    },
+                    "line" => if let (Some(counts), Some((_, lines))) = (line.take(), &mut clues) {
+                        lines.push(counts);
+                    },
+                    "clues" => match clues.take() {
+                        Some((clue_type, lines)) if clue_type == "rows"    => row_clues = Some(lines),
+                        Some((clue_type, lines)) if clue_type == "columns" => col_clues = Some(lines),
+                        Some((clue_type, _))     => return Err(Error::Logic(format!(
+                                                        "Unknown webpbn clue type {:?}; expected rows or columns", clue_type))),
+                        None                     => {},
+                    },
+                    "puzzle" => break,
+                    _ => {},
+                },
+                _ => {},
+            }
+        }
+
+        match (row_clues, col_clues) {
+            (Some(row_clues), Some(col_clues)) => Puzzle::from_clues(row_clues, col_clues),
+            (None, _) => Err(Error::Logic("webpbn XML contains no row clues".to_string())),
+            (_, None) => Err(Error::Logic("webpbn XML contains no column clues".to_string())),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "webpbn"))]
+mod tests {
+    use super::*;
+    use super::super::row::Row;
+
+    fn clues(puzzle: &Puzzle) -> (Vec<Vec<usize>>, Vec<Vec<usize>>) {
+        // the run lengths of the puzzle's rows and columns
+        let lengths = |rows: &[Row]| rows.iter().map(|row| row.runs().iter().map(|run| run.length).collect())
+                                         .collect::<Vec<Vec<usize>>>();
+        (lengths(&puzzle.rows), lengths(&puzzle.cols))
+    }
+
+    #[test]
+    fn minimal_puzzle() {
+        let puzzle = Puzzle::from_webpbn_xml(r#"<?xml version="1.0"?>
+            <puzzleset><puzzle>
+                <clues type="columns"><line><count>2</count></line><line><count>1</count></line></clues>
+                <clues type="rows"><line><count>1</count></line><line><count>2</count></line></clues>
+            </puzzle></puzzleset>"#).unwrap();
+        assert_eq!(clues(&puzzle), (vec![vec![1], vec![2]], vec![vec![2], vec![1]]));
+    }
+
+    #[test]
+    fn title_and_colors_are_ignored() {
+        let puzzle = Puzzle::from_webpbn_xml(r#"<?xml version="1.0"?>
+            <puzzleset>
+            <source>webpbn.com</source>
+            <puzzle type="grid" defaultcolor="white">
+                <title>Line 1</title>
+                <author>Someone</author>
+                <color name="white" char=".">fff</color>
+                <color name="black" char="X">000</color>
+                <clues type="columns"><line><count>1</count></line><line></line><line><count>1</count></line></clues>
+                <clues type="rows"><line><count color="black">1</count><count>1</count></line></clues>
+                <solution type="goal"><image>|X.X|</image></solution>
+            </puzzle>
+            </puzzleset>"#).unwrap();
+        assert_eq!(clues(&puzzle), (vec![vec![1, 1]], vec![vec![1], vec![], vec![1]]));
+    }
+
+    #[test]
+    fn non_numeric_count_is_an_error() {
+        let result = Puzzle::from_webpbn_xml(r#"<puzzleset><puzzle>
+                <clues type="columns"><line><count>1</count></line></clues>
+                <clues type="rows"><line><count>one</count></line></clues>
+            </puzzle></puzzleset>"#);
+        assert!(matches!(result, Err(Error::Logic(_))));
+    }
+
+    #[test]
+    fn missing_column_clues_are_an_error() {
+        let result = Puzzle::from_webpbn_xml(r#"<puzzleset><puzzle>
+                <clues type="rows"><line><count>1</count></line></clues>
+            </puzzle></puzzleset>"#);
+        assert!(matches!(result, Err(Error::Logic(ref msg)) if msg.contains("column")));
+    }
+}